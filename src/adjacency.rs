@@ -0,0 +1,191 @@
+use std::{collections::BTreeSet, fs, io, path::Path, sync::Arc};
+
+use druid::{im::Vector, Data, Lens};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    data::Canvas,
+    transform::Transform,
+    wave::{opposite, Rules},
+};
+
+/// The file the hand-edited rules are kept in, inside the tileset folder.
+pub const RULES_FILE: &str = "rules.json";
+
+/// How the hand-edited rules combine with the ones derived from matching edges.
+#[derive(Clone, Copy, Data, Debug, PartialEq, Serialize, Deserialize)]
+pub enum RuleMode {
+    /// Only edges decide; the hand-edited rules are ignored.
+    Derived,
+    /// Only the hand-edited rules decide.
+    Custom,
+    /// Both have to allow a neighbour.
+    Restrict,
+}
+
+/// Which modules may sit next to which, edited by hand in the rule editor.
+///
+/// Rules are kept by group-qualified module name (see `Canvas::key`) so they
+/// survive reloading the tileset. They name the sides of the untransformed module.
+#[derive(Clone, Data, Lens)]
+pub struct AdjacencyRules {
+    pub mode: RuleMode,
+    /// `(from, dir, to)`: `to` may be placed in direction `dir` of `from`.
+    pub allowed: Arc<BTreeSet<(String, usize, String)>>,
+    pub dirty: bool,
+}
+
+#[derive(Serialize, Deserialize)]
+struct RulesFile {
+    mode: RuleMode,
+    allowed: Vec<RuleEntry>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct RuleEntry {
+    from: String,
+    direction: usize,
+    to: String,
+}
+
+impl AdjacencyRules {
+    pub fn new() -> Self {
+        Self {
+            mode: RuleMode::Derived,
+            allowed: Arc::new(BTreeSet::new()),
+            dirty: false,
+        }
+    }
+
+    pub fn allows(&self, from: &str, dir: usize, to: &str) -> bool {
+        self.allowed
+            .contains(&(from.to_string(), dir, to.to_string()))
+    }
+
+    /// How many modules may be placed in direction `dir` of `from`.
+    pub fn count(&self, from: &str, dir: usize) -> usize {
+        self.allowed
+            .iter()
+            .filter(|(f, d, _)| f == from && *d == dir)
+            .count()
+    }
+
+    pub fn toggle(&mut self, from: &str, dir: usize, to: &str) {
+        let rule = (from.to_string(), dir, to.to_string());
+        let allowed = Arc::make_mut(&mut self.allowed);
+        if !allowed.remove(&rule) {
+            allowed.insert(rule);
+        }
+        self.dirty = true;
+    }
+
+    /// Adds the mirror image of every rule, so that `b` below `a` also allows `a`
+    /// above `b`.
+    pub fn make_symmetric(&mut self) {
+        let mirrored: Vec<_> = self
+            .allowed
+            .iter()
+            .map(|(from, dir, to)| (to.clone(), opposite(*dir), from.clone()))
+            .collect();
+        Arc::make_mut(&mut self.allowed).extend(mirrored);
+        self.dirty = true;
+    }
+
+    /// Replaces the rules with the edge-derived ones between untransformed modules.
+    pub fn copy_derived(&mut self, modules: &Vector<Canvas>) {
+        let rules = Rules::from_modules(modules);
+        let mut allowed = BTreeSet::new();
+        for a in modules.iter() {
+            for b in modules.iter() {
+                let tiles = (
+                    rules.tile_index(a.id, Transform::IDENTITY),
+                    rules.tile_index(b.id, Transform::IDENTITY),
+                );
+                if let (Some(ta), Some(tb)) = tiles {
+                    for dir in 0..4 {
+                        if rules.compatible(dir, ta, tb) {
+                            allowed.insert((a.key(), dir, b.key()));
+                        }
+                    }
+                }
+            }
+        }
+        self.allowed = Arc::new(allowed);
+        self.dirty = true;
+    }
+
+    pub fn clear(&mut self) {
+        self.allowed = Arc::new(BTreeSet::new());
+        self.dirty = true;
+    }
+
+    pub fn rename(&mut self, old: &str, new: &str) {
+        let rename = |name: &String| {
+            if name == old {
+                new.to_string()
+            } else {
+                name.clone()
+            }
+        };
+        let renamed = self
+            .allowed
+            .iter()
+            .map(|(from, dir, to)| (rename(from), *dir, rename(to)))
+            .collect();
+        self.allowed = Arc::new(renamed);
+        self.dirty = true;
+    }
+
+    /// Drops every rule that mentions `key`, for a module that was deleted.
+    pub fn remove(&mut self, key: &str) {
+        let before = self.allowed.len();
+        let kept = self
+            .allowed
+            .iter()
+            .filter(|(from, _, to)| from != key && to != key)
+            .cloned()
+            .collect();
+        self.allowed = Arc::new(kept);
+        self.dirty |= self.allowed.len() != before;
+    }
+
+    /// The rules saved in the tileset folder `dir`, or none if there are none.
+    pub fn load(dir: &Path) -> Self {
+        let file: Option<RulesFile> = fs::read_to_string(dir.join(RULES_FILE))
+            .ok()
+            .and_then(|json| serde_json::from_str(&json).ok());
+        match file {
+            Some(file) => Self {
+                mode: file.mode,
+                allowed: Arc::new(
+                    file.allowed
+                        .into_iter()
+                        .map(|rule| (rule.from, rule.direction, rule.to))
+                        .collect(),
+                ),
+                dirty: false,
+            },
+            None => Self::new(),
+        }
+    }
+
+    pub fn save(&mut self, dir: &Path) -> io::Result<()> {
+        let file = RulesFile {
+            mode: self.mode,
+            allowed: self
+                .allowed
+                .iter()
+                .map(|(from, direction, to)| RuleEntry {
+                    from: from.clone(),
+                    direction: *direction,
+                    to: to.clone(),
+                })
+                .collect(),
+        };
+        fs::create_dir_all(dir)?;
+        let json = serde_json::to_string_pretty(&file)?;
+        fs::write(dir.join(RULES_FILE), json)?;
+        self.dirty = false;
+        Ok(())
+    }
+}
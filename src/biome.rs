@@ -0,0 +1,194 @@
+use std::{
+    collections::HashMap,
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use druid::im::Vector;
+use image::RgbImage;
+use rand::{rngs::StdRng, SeedableRng};
+
+use crate::{
+    adjacency::AdjacencyRules,
+    data::{load_tileset, Canvas, MapCell, MAX_ATTEMPTS},
+    wave::{Rules, Wave, OFFSETS},
+    world::compose,
+};
+
+/// The file biome expansion settings are kept in, inside the tileset folder.
+pub const BIOMES_FILE: &str = "biomes.txt";
+
+/// How the modules of this tileset, used as biomes, expand into detail tiles.
+pub struct BiomePlan {
+    /// Each biome cell becomes `block` by `block` detail cells.
+    pub block: usize,
+    /// The detail tileset folder of each biome module, by module name.
+    pub tilesets: HashMap<String, PathBuf>,
+}
+
+/// Reads a plan, one setting per line, with folders relative to `dir`. Blank
+/// lines and text after `#` are ignored.
+///
+/// ```text
+/// block 4
+/// forest ../forest
+/// water ../water
+/// ```
+pub fn parse(text: &str, dir: &Path) -> Result<BiomePlan, String> {
+    let mut plan = BiomePlan {
+        block: 4,
+        tilesets: HashMap::new(),
+    };
+    for (i, line) in text.lines().enumerate() {
+        let line = line.split('#').next().unwrap_or("").trim();
+        if line.is_empty() {
+            continue;
+        }
+        let mut words = line.splitn(2, char::is_whitespace);
+        let (key, value) = match (words.next(), words.next()) {
+            (Some(key), Some(value)) => (key, value.trim()),
+            _ => return Err(format!("line {}: expected a name and a value", i + 1)),
+        };
+        if key == "block" {
+            plan.block = match value.parse() {
+                Ok(block) if block > 0 => block,
+                _ => return Err(format!("line {}: {} is not a block size", i + 1, value)),
+            };
+        } else {
+            plan.tilesets.insert(key.to_string(), dir.join(value));
+        }
+    }
+    Ok(plan)
+}
+
+/// Reads the plan saved in `dir`, or nothing if there is none.
+pub fn load(dir: &Path) -> String {
+    fs::read_to_string(dir.join(BIOMES_FILE)).unwrap_or_default()
+}
+
+pub fn save(dir: &Path, text: &str) -> io::Result<()> {
+    fs::create_dir_all(dir)?;
+    fs::write(dir.join(BIOMES_FILE), text)
+}
+
+/// Expands a map of biome modules into a map `plan.block` times larger, filled
+/// with tiles from each biome's detail tileset.
+///
+/// All detail tilesets are solved as one wave. Within a set the set's own
+/// `rules.json` applies; tiles of different sets meet by matching edges and
+/// sockets. Cells inside a block may only use their own biome's tiles; cells
+/// along a block edge facing another biome may also use that biome's tiles, so
+/// the two blend across the seam.
+pub fn expand(
+    map: &Vector<Vector<MapCell>>,
+    biomes: &Vector<Canvas>,
+    plan: &BiomePlan,
+    seed: u64,
+) -> Result<RgbImage, String> {
+    let rows = map.len();
+    let cols = map.get(0).map_or(0, |row| row.len());
+
+    // Load each detail tileset once, with ids that do not clash between sets.
+    let mut sets: Vec<&PathBuf> = vec![];
+    let mut set_rules: Vec<(Vector<Canvas>, AdjacencyRules)> = vec![];
+    let mut modules: Vector<Canvas> = Vector::new();
+    let mut module_set = HashMap::new();
+    let mut cell_sets = vec![vec![0; cols]; rows];
+    for (row, cells) in map.iter().enumerate() {
+        for (col, cell) in cells.iter().enumerate() {
+            let name = biomes
+                .iter()
+                .find(|module| module.id == cell.module_id)
+                .map(|module| module.name.as_str())
+                .ok_or_else(|| format!("cell {}, {} is empty", row, col))?;
+            let path = plan
+                .tilesets
+                .get(name)
+                .ok_or_else(|| format!("no detail tileset for biome {}", name))?;
+            cell_sets[row][col] = match sets.iter().position(|set| *set == path) {
+                Some(set) => set,
+                None => {
+                    let first_id = modules
+                        .iter()
+                        .map(|module| module.id + 1)
+                        .max()
+                        .unwrap_or(0);
+                    let (loaded, _) = load_tileset(path, first_id);
+                    if loaded.is_empty() {
+                        return Err(format!("no images in {}", path.display()));
+                    }
+                    for module in loaded.iter() {
+                        module_set.insert(module.id, sets.len());
+                    }
+                    set_rules.push((loaded.clone(), AdjacencyRules::load(path)));
+                    modules.append(loaded);
+                    sets.push(path);
+                    sets.len() - 1
+                }
+            };
+        }
+    }
+    let size = (modules[0].width, modules[0].height);
+    if modules
+        .iter()
+        .any(|module| (module.width, module.height) != size)
+    {
+        return Err("detail tilesets must share one tile size".to_string());
+    }
+
+    let rules = Rules::combine(&set_rules);
+    let block = plan.block;
+    let (detail_rows, detail_cols) = (rows * block, cols * block);
+    for attempt in 0..MAX_ATTEMPTS {
+        let mut rng = StdRng::seed_from_u64(seed.wrapping_add(attempt));
+        let mut wave = Wave::new(&rules, detail_rows, detail_cols);
+        let mut restricted = Ok(());
+        for row in 0..detail_rows {
+            for col in 0..detail_cols {
+                let (biome_row, biome_col) = (row / block, col / block);
+                let mut allowed_sets = vec![cell_sets[biome_row][biome_col]];
+                let at_edge = [
+                    row % block == 0,
+                    col % block == block - 1,
+                    row % block == block - 1,
+                    col % block == 0,
+                ];
+                for (dir, &(dr, dc)) in OFFSETS.iter().enumerate() {
+                    let next_row = biome_row as isize + dr;
+                    let next_col = biome_col as isize + dc;
+                    if at_edge[dir]
+                        && next_row >= 0
+                        && next_col >= 0
+                        && (next_row as usize) < rows
+                        && (next_col as usize) < cols
+                    {
+                        allowed_sets.push(cell_sets[next_row as usize][next_col as usize]);
+                    }
+                }
+                let allowed: Vec<bool> = rules
+                    .tiles
+                    .iter()
+                    .map(|tile| allowed_sets.contains(&module_set[&tile.module_id]))
+                    .collect();
+                restricted = restricted.and_then(|_| wave.restrict(row, col, &allowed));
+            }
+        }
+        if restricted.is_err() {
+            return Err("the detail tilesets have no tiles that fit together".to_string());
+        }
+        if wave.collapse(&mut rng).is_err() {
+            continue;
+        }
+        let cells: Vec<Option<Canvas>> = wave
+            .result()
+            .into_iter()
+            .flatten()
+            .map(|tile| Some(rules.tiles[tile].canvas.clone()))
+            .collect();
+        return Ok(compose(detail_cols, detail_rows, &cells));
+    }
+    Err(format!(
+        "no detail map found after {} attempts",
+        MAX_ATTEMPTS
+    ))
+}
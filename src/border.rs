@@ -0,0 +1,161 @@
+use druid::{Data, Lens};
+
+use crate::{
+    constraints::{Context, GlobalConstraint},
+    socket::Socket,
+    wave::{Contradiction, Wave},
+};
+
+/// What may lie along one edge of the map.
+#[derive(Clone, Copy, Data, Debug, PartialEq)]
+pub enum EdgeKind {
+    Any,
+    /// Only the modules named in `MapEdge::modules`.
+    Modules,
+    /// Only tiles whose outward side connects to `MapEdge::socket`, as if a tile
+    /// with that socket lay beyond the edge. Sides without a socket label never do.
+    Socket,
+}
+
+#[derive(Clone, Data, Lens)]
+pub struct MapEdge {
+    pub kind: EdgeKind,
+    /// Comma separated module names, qualified by group (see `Canvas::key`) for
+    /// modules in a group.
+    pub modules: String,
+    pub socket: Socket,
+}
+
+impl MapEdge {
+    pub fn new() -> Self {
+        Self {
+            kind: EdgeKind::Any,
+            modules: String::new(),
+            socket: Socket::empty(),
+        }
+    }
+
+    pub fn module_names(&self) -> Vec<String> {
+        self.modules
+            .split(',')
+            .map(str::trim)
+            .filter(|name| !name.is_empty())
+            .map(str::to_string)
+            .collect()
+    }
+
+    /// Which tiles may sit along the edge on side `dir`, or `None` if any may.
+    fn allowed(&self, ctx: &Context, dir: usize) -> Option<Vec<bool>> {
+        match self.kind {
+            EdgeKind::Any => None,
+            EdgeKind::Modules => Some(ctx.tiles_of(&self.module_names())),
+            EdgeKind::Socket => Some(
+                ctx.rules
+                    .tiles
+                    .iter()
+                    .map(|tile| tile.canvas.sockets[dir].connects(&self.socket))
+                    .collect(),
+            ),
+        }
+    }
+}
+
+/// Settings for the four edges of the finite map.
+#[derive(Clone, Data, Lens)]
+pub struct MapBorder {
+    pub top: MapEdge,
+    pub right: MapEdge,
+    pub bottom: MapEdge,
+    pub left: MapEdge,
+}
+
+impl MapBorder {
+    pub fn new() -> Self {
+        Self {
+            top: MapEdge::new(),
+            right: MapEdge::new(),
+            bottom: MapEdge::new(),
+            left: MapEdge::new(),
+        }
+    }
+
+    /// The edge on side `dir`, in up, right, down, left order.
+    pub fn edge(&self, dir: usize) -> &MapEdge {
+        match dir {
+            0 => &self.top,
+            1 => &self.right,
+            2 => &self.bottom,
+            _ => &self.left,
+        }
+    }
+
+    pub fn edge_mut(&mut self, dir: usize) -> &mut MapEdge {
+        match dir {
+            0 => &mut self.top,
+            1 => &mut self.right,
+            2 => &mut self.bottom,
+            _ => &mut self.left,
+        }
+    }
+}
+
+/// The sides of a `rows` by `cols` map that cell (`row`, `col`) lies on, in up,
+/// right, down, left order.
+fn sides_of(row: usize, col: usize, rows: usize, cols: usize) -> [bool; 4] {
+    [row == 0, col == cols - 1, row == rows - 1, col == 0]
+}
+
+/// The border is a constraint like the ones in the constraints file, so both are
+/// enforced the same way. A wrapping map has no edges, so it is ignored there.
+impl GlobalConstraint for MapBorder {
+    fn prepare(&self, ctx: &Context, wave: &mut Wave) -> Result<(), Contradiction> {
+        if ctx.wraps {
+            return Ok(());
+        }
+        for dir in 0..4 {
+            let allowed = match self.edge(dir).allowed(ctx, dir) {
+                Some(allowed) => allowed,
+                None => continue,
+            };
+            for row in 0..ctx.rows {
+                for col in 0..ctx.cols {
+                    if sides_of(row, col, ctx.rows, ctx.cols)[dir] {
+                        ctx.restrict(wave, row, col, &allowed)?;
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Only module lists can be checked on module ids; socket edges are fully
+    /// enforced by `prepare`.
+    fn check(&self, ctx: &Context, map: &[Vec<usize>]) -> Option<String> {
+        if ctx.wraps {
+            return None;
+        }
+        let names: Vec<Vec<String>> = (0..4).map(|dir| self.edge(dir).module_names()).collect();
+        let mut wrong = 0;
+        for (row, cells) in map.iter().enumerate() {
+            for (col, &module_id) in cells.iter().enumerate() {
+                let sides = sides_of(row, col, ctx.rows, ctx.cols);
+                let fits = (0..4).all(|dir| {
+                    !sides[dir]
+                        || self.edge(dir).kind != EdgeKind::Modules
+                        || ctx.is_in(module_id, &names[dir])
+                });
+                if !fits {
+                    wrong += 1;
+                }
+            }
+        }
+        if wrong == 0 {
+            None
+        } else {
+            Some(format!(
+                "{} border cells hold modules their edge does not allow",
+                wrong
+            ))
+        }
+    }
+}
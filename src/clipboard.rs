@@ -0,0 +1,25 @@
+use druid::{Application, ClipboardFormat, FormatId};
+use image::{DynamicImage, ImageFormat, ImageOutputFormat, RgbImage};
+
+#[cfg(target_os = "macos")]
+const PNG_FORMAT: FormatId = "public.png";
+#[cfg(not(target_os = "macos"))]
+const PNG_FORMAT: FormatId = "image/png";
+
+/// Puts `image` on the system clipboard as a PNG.
+pub fn put_image(image: &RgbImage) {
+    let mut png = vec![];
+    DynamicImage::ImageRgb8(image.clone())
+        .write_to(&mut png, ImageOutputFormat::Png)
+        .unwrap();
+    Application::global()
+        .clipboard()
+        .put_formats(&[ClipboardFormat::new(PNG_FORMAT, png)]);
+}
+
+/// The PNG on the system clipboard, if there is one.
+pub fn get_image() -> Option<RgbImage> {
+    let png = Application::global().clipboard().get_format(PNG_FORMAT)?;
+    let image = image::load_from_memory_with_format(&png, ImageFormat::Png).ok()?;
+    Some(image.into_rgb8())
+}
@@ -0,0 +1,463 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    fs, io,
+    path::Path,
+};
+
+use druid::im::Vector;
+
+use crate::{
+    border::{EdgeKind, MapBorder, MapEdge},
+    data::{Canvas, MapRect},
+    socket::Socket,
+    wave::{wrap, Contradiction, Rules, Wave, OFFSETS},
+};
+
+/// The file global constraints are kept in, inside the tileset folder.
+pub const CONSTRAINTS_FILE: &str = "constraints.txt";
+
+/// What a constraint can see of the generation it takes part in.
+pub struct Context<'a> {
+    pub rules: &'a Rules,
+    /// The key of each module (see `Canvas::key`) by id.
+    pub keys: HashMap<usize, String>,
+    /// The cells being generated, within the whole map.
+    pub region: MapRect,
+    pub rows: usize,
+    pub cols: usize,
+    pub wraps: bool,
+}
+
+impl<'a> Context<'a> {
+    pub fn new(
+        rules: &'a Rules,
+        modules: &Vector<Canvas>,
+        region: MapRect,
+        rows: usize,
+        cols: usize,
+        wraps: bool,
+    ) -> Self {
+        Self {
+            rules,
+            keys: modules
+                .iter()
+                .map(|module| (module.id, module.key()))
+                .collect(),
+            region,
+            rows,
+            cols,
+            wraps,
+        }
+    }
+
+    pub fn is_in(&self, module_id: usize, modules: &[String]) -> bool {
+        self.keys
+            .get(&module_id)
+            .is_some_and(|key| modules.contains(key))
+    }
+
+    /// Which tiles belong to one of `modules`.
+    pub fn tiles_of(&self, modules: &[String]) -> Vec<bool> {
+        self.rules
+            .tiles
+            .iter()
+            .map(|tile| self.is_in(tile.module_id, modules))
+            .collect()
+    }
+
+    /// Narrows map cell (`row`, `col`) to `allowed` if it is being generated.
+    pub fn restrict(
+        &self,
+        wave: &mut Wave,
+        row: usize,
+        col: usize,
+        allowed: &[bool],
+    ) -> Result<(), Contradiction> {
+        let region = self.region;
+        if region.contains(row, col) {
+            wave.restrict(row - region.row, col - region.col, allowed)?;
+        }
+        Ok(())
+    }
+}
+
+/// A rule the whole map has to satisfy, beyond which tiles may sit side by side.
+///
+/// Constraints can narrow the wave before it collapses; whatever they cannot
+/// enforce that way is checked on the finished map, and generation retries when
+/// the check fails.
+pub trait GlobalConstraint {
+    fn prepare(&self, _ctx: &Context, _wave: &mut Wave) -> Result<(), Contradiction> {
+        Ok(())
+    }
+
+    /// How `map`, given as module ids, breaks the constraint, if it does.
+    fn check(&self, ctx: &Context, map: &[Vec<usize>]) -> Option<String>;
+}
+
+/// `count <modules> <min> <max>`: how many cells may hold the modules. Bounds
+/// ending in `%` are shares of the whole map.
+struct Count {
+    modules: Vec<String>,
+    min: usize,
+    max: usize,
+    percent: bool,
+}
+
+impl GlobalConstraint for Count {
+    fn prepare(&self, ctx: &Context, wave: &mut Wave) -> Result<(), Contradiction> {
+        if self.max > 0 {
+            return Ok(());
+        }
+        let allowed: Vec<bool> = ctx.tiles_of(&self.modules).iter().map(|t| !t).collect();
+        for row in 0..ctx.rows {
+            for col in 0..ctx.cols {
+                ctx.restrict(wave, row, col, &allowed)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn check(&self, ctx: &Context, map: &[Vec<usize>]) -> Option<String> {
+        let count = map
+            .iter()
+            .flatten()
+            .filter(|&&module_id| ctx.is_in(module_id, &self.modules))
+            .count();
+        let (min, max) = if self.percent {
+            let cells = ctx.rows * ctx.cols;
+            ((self.min * cells).div_ceil(100), self.max * cells / 100)
+        } else {
+            (self.min, self.max)
+        };
+        if count < min || count > max {
+            Some(format!(
+                "{} cells of {}, expected {} to {}",
+                count,
+                self.modules.join(","),
+                min,
+                max
+            ))
+        } else {
+            None
+        }
+    }
+}
+
+/// `place <modules> <row> <col>`: the cell must hold one of the modules.
+struct Place {
+    modules: Vec<String>,
+    row: usize,
+    col: usize,
+}
+
+impl GlobalConstraint for Place {
+    fn prepare(&self, ctx: &Context, wave: &mut Wave) -> Result<(), Contradiction> {
+        ctx.restrict(wave, self.row, self.col, &ctx.tiles_of(&self.modules))
+    }
+
+    fn check(&self, ctx: &Context, map: &[Vec<usize>]) -> Option<String> {
+        if self.row >= ctx.rows || self.col >= ctx.cols {
+            return Some(format!(
+                "cell {}, {} is outside the {}x{} map",
+                self.row, self.col, ctx.cols, ctx.rows
+            ));
+        }
+        if ctx.is_in(map[self.row][self.col], &self.modules) {
+            None
+        } else {
+            Some(format!(
+                "cell {}, {} is not {}",
+                self.row,
+                self.col,
+                self.modules.join(",")
+            ))
+        }
+    }
+}
+
+/// `connected <modules>`: the cells holding the modules must form one area.
+struct Connected {
+    modules: Vec<String>,
+}
+
+impl GlobalConstraint for Connected {
+    fn check(&self, ctx: &Context, map: &[Vec<usize>]) -> Option<String> {
+        let walkable = |row: usize, col: usize| ctx.is_in(map[row][col], &self.modules);
+        let mut seen = vec![vec![false; ctx.cols]; ctx.rows];
+        let mut areas = 0;
+        for row in 0..ctx.rows {
+            for col in 0..ctx.cols {
+                if seen[row][col] || !walkable(row, col) {
+                    continue;
+                }
+                areas += 1;
+                seen[row][col] = true;
+                let mut queue = VecDeque::from(vec![(row, col)]);
+                while let Some((row, col)) = queue.pop_front() {
+                    for &(dr, dc) in OFFSETS.iter() {
+                        let next_row = wrap(row as isize + dr, ctx.rows, ctx.wraps);
+                        let next_col = wrap(col as isize + dc, ctx.cols, ctx.wraps);
+                        if let (Some(r), Some(c)) = (next_row, next_col) {
+                            if !seen[r][c] && walkable(r, c) {
+                                seen[r][c] = true;
+                                queue.push_back((r, c));
+                            }
+                        }
+                    }
+                }
+            }
+        }
+        if areas > 1 {
+            Some(format!(
+                "{} cells form {} separate areas",
+                self.modules.join(","),
+                areas
+            ))
+        } else {
+            None
+        }
+    }
+}
+
+/// Reads constraints, one per line. Blank lines and text after `#` are ignored.
+///
+/// ```text
+/// count castle 1 1
+/// count water 0% 5%
+/// place castle 8 8
+/// border water top bottom
+/// connected grass,road
+/// ```
+pub fn parse(
+    text: &str,
+    modules: &Vector<Canvas>,
+) -> Result<Vec<Box<dyn GlobalConstraint>>, String> {
+    let mut constraints: Vec<Box<dyn GlobalConstraint>> = vec![];
+    for (i, line) in text.lines().enumerate() {
+        let line = line.split('#').next().unwrap_or("").trim();
+        if line.is_empty() {
+            continue;
+        }
+        let constraint =
+            parse_line(line, modules).map_err(|error| format!("line {}: {}", i + 1, error))?;
+        constraints.push(constraint);
+    }
+    Ok(constraints)
+}
+
+fn parse_line(line: &str, modules: &Vector<Canvas>) -> Result<Box<dyn GlobalConstraint>, String> {
+    let words: Vec<&str> = line.split_whitespace().collect();
+    let names = |word: Option<&&str>| -> Result<Vec<String>, String> {
+        let word = word.ok_or("expected module names")?;
+        word.split(',')
+            .map(|name| module_key(modules, name))
+            .collect()
+    };
+    let number = |word: Option<&&str>| -> Result<usize, String> {
+        let word = word.ok_or("expected a number")?;
+        word.trim_end_matches('%')
+            .parse()
+            .map_err(|_| format!("{} is not a number", word))
+    };
+
+    match words[0] {
+        "count" => {
+            let modules = names(words.get(1))?;
+            let (min, max) = (number(words.get(2))?, number(words.get(3))?);
+            let percent = words[2].ends_with('%');
+            if percent != words[3].ends_with('%') {
+                return Err("both bounds must be counts or both percentages".to_string());
+            }
+            Ok(Box::new(Count {
+                modules,
+                min,
+                max,
+                percent,
+            }))
+        }
+        "place" => Ok(Box::new(Place {
+            modules: names(words.get(1))?,
+            row: number(words.get(2))?,
+            col: number(words.get(3))?,
+        })),
+        // `border <modules> [top] [right] [bottom] [left]`: a map border that
+        // only allows the modules along the given sides, or all of them. Like the
+        // border settings, it is ignored when the map wraps.
+        "border" => {
+            let edge = MapEdge {
+                kind: EdgeKind::Modules,
+                modules: names(words.get(1))?.join(","),
+                socket: Socket::empty(),
+            };
+            let mut sides = [words.len() == 2; 4];
+            for word in words.iter().skip(2) {
+                let side = ["top", "right", "bottom", "left"]
+                    .iter()
+                    .position(|side| side == word)
+                    .ok_or_else(|| format!("{} is not a side", word))?;
+                sides[side] = true;
+            }
+            let mut border = MapBorder::new();
+            for side in (0..4).filter(|&side| sides[side]) {
+                *border.edge_mut(side) = edge.clone();
+            }
+            Ok(Box::new(border))
+        }
+        "connected" => Ok(Box::new(Connected {
+            modules: names(words.get(1))?,
+        })),
+        other => Err(format!("unknown constraint {}", other)),
+    }
+}
+
+/// The key (see `Canvas::key`) of the module `name` refers to: either a
+/// group-qualified name, or a bare name that only one module has.
+fn module_key(modules: &Vector<Canvas>, name: &str) -> Result<String, String> {
+    if modules.iter().any(|module| module.key() == name) {
+        return Ok(name.to_string());
+    }
+    let keys: Vec<String> = modules
+        .iter()
+        .filter(|module| module.name == name)
+        .map(Canvas::key)
+        .collect();
+    match keys.len() {
+        0 => Err(format!("no module named {}", name)),
+        1 => Ok(keys[0].clone()),
+        _ => Err(format!("{} could be any of {}", name, keys.join(", "))),
+    }
+}
+
+/// Reads the constraints saved in `dir`, or nothing if there are none.
+pub fn load(dir: &Path) -> String {
+    fs::read_to_string(dir.join(CONSTRAINTS_FILE)).unwrap_or_default()
+}
+
+pub fn save(dir: &Path, text: &str) -> io::Result<()> {
+    fs::create_dir_all(dir)?;
+    fs::write(dir.join(CONSTRAINTS_FILE), text)
+}
+
+#[cfg(test)]
+mod tests {
+    use druid::im::vector;
+
+    use super::*;
+
+    fn modules() -> Vector<Canvas> {
+        let mut a = Canvas::new(0);
+        a.name = "a".to_string();
+        let mut b = Canvas::new(1);
+        b.name = "b".to_string();
+        vector![a, b]
+    }
+
+    fn error(text: &str) -> String {
+        match parse(text, &modules()) {
+            Ok(_) => panic!("{:?} parsed", text),
+            Err(error) => error,
+        }
+    }
+
+    #[test]
+    fn reads_well_formed_lines() {
+        let text = "# comment\n\ncount a 1 1\ncount a,b 0% 5%\nplace b 2 3\n\
+                    border a\nborder a top left\nconnected a,b";
+        assert_eq!(parse(text, &modules()).unwrap().len(), 6);
+    }
+
+    #[test]
+    fn rejects_missing_arguments() {
+        assert_eq!(error("border"), "line 1: expected module names");
+        assert_eq!(error("count"), "line 1: expected module names");
+        assert_eq!(error("count a"), "line 1: expected a number");
+        assert_eq!(error("count a 1"), "line 1: expected a number");
+        assert_eq!(error("place"), "line 1: expected module names");
+        assert_eq!(error("connected"), "line 1: expected module names");
+    }
+
+    #[test]
+    fn rejects_bad_values() {
+        assert_eq!(error("place x"), "line 1: no module named x");
+        assert_eq!(error("place a x 1"), "line 1: x is not a number");
+        assert_eq!(error("border a middle"), "line 1: middle is not a side");
+        assert_eq!(error("spawn a"), "line 1: unknown constraint spawn");
+        assert_eq!(
+            error("count a 1 1\ncount c 1 1"),
+            "line 2: no module named c"
+        );
+    }
+
+    #[test]
+    fn matches_modules_by_group() {
+        let module = |id: usize, group: &str, name: &str| {
+            let mut module = Canvas::new(id);
+            module.group = group.to_string();
+            module.name = name.to_string();
+            module
+        };
+        let modules = vector![
+            module(0, "x", "a"),
+            module(1, "y", "a"),
+            module(2, "y", "b")
+        ];
+        assert!(parse("count x/a 1 1\ncount b 1 1", &modules).is_ok());
+        assert_eq!(
+            parse("count a 1 1", &modules).err().unwrap(),
+            "line 1: a could be any of x/a, y/a"
+        );
+
+        let rules = Rules::from_modules(&modules);
+        let region = MapRect {
+            row: 0,
+            col: 0,
+            rows: 1,
+            cols: 2,
+        };
+        let ctx = Context::new(&rules, &modules, region, 1, 2, false);
+        let count = &parse("count y/a 1 1", &modules).unwrap()[0];
+        assert_eq!(count.check(&ctx, &[vec![0, 1]]), None);
+        assert!(count.check(&ctx, &[vec![1, 1]]).is_some());
+    }
+
+    #[test]
+    fn rejects_mixed_count_bounds() {
+        let mixed = "line 1: both bounds must be counts or both percentages";
+        assert_eq!(error("count a 1 5%"), mixed);
+        assert_eq!(error("count a 1% 5"), mixed);
+    }
+
+    /// Checks the first constraint in `text` against a 3x3 `map`.
+    fn check(text: &str, map: &[Vec<usize>], wraps: bool) -> Option<String> {
+        let modules = modules();
+        let rules = Rules::from_modules(&modules);
+        let region = MapRect {
+            row: 0,
+            col: 0,
+            rows: 3,
+            cols: 3,
+        };
+        let ctx = Context::new(&rules, &modules, region, 3, 3, wraps);
+        parse(text, &modules).unwrap()[0].check(&ctx, map)
+    }
+
+    #[test]
+    fn reports_placements_outside_the_map() {
+        let map = vec![vec![0; 3]; 3];
+        assert_eq!(check("place a 2 2", &map, false), None);
+        assert_eq!(
+            check("place a 100 1", &map, false),
+            Some("cell 100, 1 is outside the 3x3 map".to_string())
+        );
+    }
+
+    #[test]
+    fn border_lines_check_the_given_edges() {
+        let mut map = vec![vec![0; 3], vec![1; 3], vec![1; 3]];
+        assert_eq!(check("border a top", &map, false), None);
+        map[0][1] = 1;
+        assert!(check("border a top", &map, false).is_some());
+        assert_eq!(check("border a top", &map, true), None);
+    }
+}
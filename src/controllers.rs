@@ -1,9 +1,20 @@
 use druid::{
-    widget::Controller, ContextMenu, Data, Env, Event, EventCtx, LocalizedString, MenuDesc,
-    MenuItem, Widget,
+    commands, widget::Controller, Command, ContextMenu, Data, Env, Event, EventCtx,
+    FileDialogOptions, FileSpec, LifeCycle, LifeCycleCtx, LocalizedString, MenuDesc, MenuItem,
+    Widget,
 };
 
-use crate::data::{AppState, CLEAR_CANVAS, SAVE_CANVAS, SHOW_MAP_WINDOW};
+use crate::data::{
+    AppState, Canvas, CLEAR_CANVAS, COPY, COPY_MODULE, CUT, DELETE_MODULE, DESELECT,
+    DUPLICATE_MODULE, FLIP_SELECTION_HORIZONTAL, FLIP_SELECTION_VERTICAL, FLIP_TILE_HORIZONTAL,
+    FLIP_TILE_VERTICAL, HOVER_MODULE, IMAGE_EXTENSIONS, IMPORT_SPRITE_SHEET, MOVE_MODULE_LEFT,
+    MOVE_MODULE_RIGHT, OPEN_TILESET, PASTE, PASTE_AS_MODULE, REBUILD_MENU, RENAME_MODULE,
+    ROTATE_SELECTION, ROTATE_TILE_180, ROTATE_TILE_CLOCKWISE, ROTATE_TILE_COUNTER_CLOCKWISE,
+    SAVE_ALL, SAVE_CANVAS, SELECT_ALL, SHIFT_TILE_DOWN, SHIFT_TILE_LEFT, SHIFT_TILE_RIGHT,
+    SHIFT_TILE_UP, SHOW_EXPORT_WINDOW, SHOW_MAP_WINDOW, TRANSPOSE_TILE,
+};
+
+const IMAGE_FILE_TYPE: FileSpec = FileSpec::new("Image", &IMAGE_EXTENSIONS);
 
 pub struct ContextMenuController;
 
@@ -19,9 +30,207 @@ impl<T, W: Widget<T>> Controller<T, W> for ContextMenuController {
     }
 }
 
+/// Rebuilds the main menu when the wrapped control flips a setting the menu
+/// shows a check mark for.
+pub struct MenuCheckController;
+
+impl<W: Widget<bool>> Controller<bool, W> for MenuCheckController {
+    fn event(
+        &mut self,
+        child: &mut W,
+        ctx: &mut EventCtx,
+        event: &Event,
+        data: &mut bool,
+        env: &Env,
+    ) {
+        let before = *data;
+        child.event(ctx, event, data, env);
+        if *data != before {
+            ctx.submit_command(REBUILD_MENU);
+        }
+    }
+}
+
 fn make_context_menu<T: Data>() -> MenuDesc<T> {
     MenuDesc::empty()
         .append(MenuItem::new(LocalizedString::new("Save"), SAVE_CANVAS))
+        .append(MenuItem::new(LocalizedString::new("Save All"), SAVE_ALL))
         .append(MenuItem::new(LocalizedString::new("Clear"), CLEAR_CANVAS))
-        .append(MenuItem::new(LocalizedString::new("Show Map"), SHOW_MAP_WINDOW))
+        .append(MenuItem::new(
+            LocalizedString::new("Show Map"),
+            SHOW_MAP_WINDOW,
+        ))
+        .append(
+            MenuDesc::new(LocalizedString::new("Tile"))
+                .append(MenuItem::new(
+                    LocalizedString::new("Rotate 90° Clockwise"),
+                    ROTATE_TILE_CLOCKWISE,
+                ))
+                .append(MenuItem::new(
+                    LocalizedString::new("Rotate 90° Counter-clockwise"),
+                    ROTATE_TILE_COUNTER_CLOCKWISE,
+                ))
+                .append(MenuItem::new(
+                    LocalizedString::new("Rotate 180°"),
+                    ROTATE_TILE_180,
+                ))
+                .append(MenuItem::new(
+                    LocalizedString::new("Flip Horizontally"),
+                    FLIP_TILE_HORIZONTAL,
+                ))
+                .append(MenuItem::new(
+                    LocalizedString::new("Flip Vertically"),
+                    FLIP_TILE_VERTICAL,
+                ))
+                .append(MenuItem::new(
+                    LocalizedString::new("Transpose"),
+                    TRANSPOSE_TILE,
+                ))
+                .append_separator()
+                .append(MenuItem::new(
+                    LocalizedString::new("Shift Up"),
+                    SHIFT_TILE_UP,
+                ))
+                .append(MenuItem::new(
+                    LocalizedString::new("Shift Down"),
+                    SHIFT_TILE_DOWN,
+                ))
+                .append(MenuItem::new(
+                    LocalizedString::new("Shift Left"),
+                    SHIFT_TILE_LEFT,
+                ))
+                .append(MenuItem::new(
+                    LocalizedString::new("Shift Right"),
+                    SHIFT_TILE_RIGHT,
+                )),
+        )
+        .append_separator()
+        .append(MenuItem::new(LocalizedString::new("Cut"), CUT))
+        .append(MenuItem::new(LocalizedString::new("Copy"), COPY))
+        .append(MenuItem::new(LocalizedString::new("Paste"), PASTE))
+        .append(MenuItem::new(
+            LocalizedString::new("Copy Module"),
+            COPY_MODULE,
+        ))
+        .append(MenuItem::new(
+            LocalizedString::new("Paste as New Module"),
+            PASTE_AS_MODULE,
+        ))
+        .append(
+            MenuDesc::new(LocalizedString::new("Selection"))
+                .append(MenuItem::new(
+                    LocalizedString::new("Select All"),
+                    SELECT_ALL,
+                ))
+                .append(MenuItem::new(LocalizedString::new("Deselect"), DESELECT))
+                .append_separator()
+                .append(MenuItem::new(
+                    LocalizedString::new("Rotate 90°"),
+                    ROTATE_SELECTION,
+                ))
+                .append(MenuItem::new(
+                    LocalizedString::new("Flip Horizontally"),
+                    FLIP_SELECTION_HORIZONTAL,
+                ))
+                .append(MenuItem::new(
+                    LocalizedString::new("Flip Vertically"),
+                    FLIP_SELECTION_VERTICAL,
+                )),
+        )
+        .append_separator()
+        .append(MenuItem::new(
+            LocalizedString::new("Open Tileset..."),
+            open_tileset_command(),
+        ))
+        .append(MenuItem::new(
+            LocalizedString::new("Import Sprite Sheet..."),
+            import_sprite_sheet_command(),
+        ))
+        .append(MenuItem::new(
+            LocalizedString::new("Export Tileset..."),
+            export_tileset_command(),
+        ))
+}
+
+pub fn open_tileset_command() -> Command {
+    commands::SHOW_OPEN_PANEL.with(
+        FileDialogOptions::new()
+            .select_directories()
+            .accept_command(OPEN_TILESET),
+    )
+}
+
+pub fn import_sprite_sheet_command() -> Command {
+    commands::SHOW_OPEN_PANEL.with(
+        FileDialogOptions::new()
+            .allowed_types(vec![IMAGE_FILE_TYPE])
+            .accept_command(IMPORT_SPRITE_SHEET),
+    )
+}
+
+pub fn export_tileset_command() -> Command {
+    SHOW_EXPORT_WINDOW.into()
+}
+
+pub struct ModuleContextMenuController;
+
+impl<W: Widget<(AppState, Canvas)>> Controller<(AppState, Canvas), W>
+    for ModuleContextMenuController
+{
+    fn event(
+        &mut self,
+        child: &mut W,
+        ctx: &mut EventCtx,
+        event: &Event,
+        data: &mut (AppState, Canvas),
+        env: &Env,
+    ) {
+        match event {
+            Event::MouseDown(ref mouse) if mouse.button.is_right() => {
+                let menu = make_module_context_menu::<AppState>(data.1.id);
+                ctx.show_context_menu(ContextMenu::new(menu, mouse.window_pos));
+            }
+            _ => child.event(ctx, event, data, env),
+        }
+    }
+
+    fn lifecycle(
+        &mut self,
+        child: &mut W,
+        ctx: &mut LifeCycleCtx,
+        event: &LifeCycle,
+        data: &(AppState, Canvas),
+        env: &Env,
+    ) {
+        if let LifeCycle::HotChanged(hot) = event {
+            let module = if *hot { Some(data.1.id) } else { None };
+            ctx.submit_command(HOVER_MODULE.with(module));
+        }
+        child.lifecycle(ctx, event, data, env)
+    }
+}
+
+fn make_module_context_menu<T: Data>(id: usize) -> MenuDesc<T> {
+    MenuDesc::empty()
+        .append(MenuItem::new(
+            LocalizedString::new("Rename"),
+            RENAME_MODULE.with(id),
+        ))
+        .append(MenuItem::new(
+            LocalizedString::new("Duplicate"),
+            DUPLICATE_MODULE.with(id),
+        ))
+        .append(MenuItem::new(
+            LocalizedString::new("Move Left"),
+            MOVE_MODULE_LEFT.with(id),
+        ))
+        .append(MenuItem::new(
+            LocalizedString::new("Move Right"),
+            MOVE_MODULE_RIGHT.with(id),
+        ))
+        .append_separator()
+        .append(MenuItem::new(
+            LocalizedString::new("Delete"),
+            DELETE_MODULE.with(id),
+        ))
 }
@@ -1,24 +1,107 @@
 use std::{
-    fs,
+    cmp::Ordering,
+    collections::{BTreeMap, HashMap},
+    fs, io,
     ops::{Index, IndexMut},
-    path::PathBuf,
+    path::{Path, PathBuf},
+    sync::Arc,
 };
 
 use druid::{
     im::{vector, Vector},
-    piet::ImageFormat,
-    Env, EventCtx, ImageBuf, Selector,
+    Env, EventCtx, FileInfo, Selector,
 };
 use druid::{Color, Data, Lens};
-use image::{imageops, DynamicImage, GrayImage, ImageBuffer, Pixel, RgbImage};
-use rand::Rng;
+use image::{DynamicImage, ImageBuffer, Pixel, RgbImage};
+use rand::{rngs::StdRng, Rng, SeedableRng};
+
+use crate::{
+    adjacency::AdjacencyRules,
+    biome,
+    border::MapBorder,
+    constraints::{self, Context, GlobalConstraint},
+    diagnostics::{self, Diagnostics, Run},
+    export::ExportState,
+    import::ImportState,
+    lint::{self, Finding},
+    shortcuts::{self, Shortcut},
+    socket::{self, Socket},
+    transform::Transform,
+    wave::{opposite, wrap, Contradiction, Rules, Wave, OFFSETS},
+    world::{compose, World},
+};
 
 pub const CANVAS_SIZE: usize = 8;
 pub const MAP_SIZE: usize = 16;
+pub const MAX_ATTEMPTS: u64 = 10;
+pub const UNDO_LIMIT: usize = 50;
+pub const TILE_IMAGES_DIR: &str = "tile_images";
+pub const IMAGE_EXTENSIONS: [&str; 8] = ["png", "bmp", "gif", "jpg", "jpeg", "tga", "tif", "tiff"];
 
 pub const SAVE_CANVAS: Selector = Selector::new("tile-wave.save-canvas");
+pub const SAVE_ALL: Selector = Selector::new("tile-wave.save-all");
+pub const OPEN_PENDING_TILESET: Selector<bool> = Selector::new("tile-wave.open-pending-tileset");
+pub const QUIT: Selector<bool> = Selector::new("tile-wave.quit");
 pub const CLEAR_CANVAS: Selector = Selector::new("tile-wave.clear-canvas");
 pub const SHOW_MAP_WINDOW: Selector = Selector::new("tile-wave.show-map-window");
+pub const GENERATE_MAP: Selector = Selector::new("tile-wave.generate-map");
+pub const REGENERATE_UNPINNED: Selector = Selector::new("tile-wave.regenerate-unpinned");
+pub const REGENERATE_SELECTION: Selector = Selector::new("tile-wave.regenerate-selection");
+pub const UNDO: Selector = Selector::new("tile-wave.undo");
+pub const NEW_WORLD: Selector = Selector::new("tile-wave.new-world");
+pub const SHOW_REGION_WINDOW: Selector = Selector::new("tile-wave.show-region-window");
+pub const EXPORT_REGION: Selector<FileInfo> = Selector::new("tile-wave.export-region");
+pub const DELETE_MODULE: Selector<usize> = Selector::new("tile-wave.delete-module");
+pub const DUPLICATE_MODULE: Selector<usize> = Selector::new("tile-wave.duplicate-module");
+pub const RENAME_MODULE: Selector<usize> = Selector::new("tile-wave.rename-module");
+pub const MOVE_MODULE_LEFT: Selector<usize> = Selector::new("tile-wave.move-module-left");
+pub const MOVE_MODULE_RIGHT: Selector<usize> = Selector::new("tile-wave.move-module-right");
+pub const APPLY_RENAME: Selector = Selector::new("tile-wave.apply-rename");
+pub const OPEN_TILESET: Selector<FileInfo> = Selector::new("tile-wave.open-tileset");
+pub const IMPORT_SPRITE_SHEET: Selector<FileInfo> = Selector::new("tile-wave.import-sprite-sheet");
+pub const APPLY_IMPORT: Selector = Selector::new("tile-wave.apply-import");
+pub const SHOW_EXPORT_WINDOW: Selector = Selector::new("tile-wave.show-export-window");
+pub const EXPORT_TILESET: Selector<FileInfo> = Selector::new("tile-wave.export-tileset");
+pub const NEW_MODULE: Selector = Selector::new("tile-wave.new-module");
+pub const NEXT_MODULE: Selector = Selector::new("tile-wave.next-module");
+pub const PREVIOUS_MODULE: Selector = Selector::new("tile-wave.previous-module");
+pub const SELECT_PALETTE_COLOR: Selector<usize> = Selector::new("tile-wave.select-palette-color");
+pub const TOGGLE_PIXEL_GRID: Selector = Selector::new("tile-wave.toggle-pixel-grid");
+pub const TOGGLE_TILE_PREVIEW: Selector = Selector::new("tile-wave.toggle-tile-preview");
+pub const SHOW_SHORTCUTS_WINDOW: Selector = Selector::new("tile-wave.show-shortcuts-window");
+pub const APPLY_SHORTCUTS: Selector = Selector::new("tile-wave.apply-shortcuts");
+pub const REBUILD_MENU: Selector = Selector::new("tile-wave.rebuild-menu");
+pub const ROTATE_TILE_CLOCKWISE: Selector = Selector::new("tile-wave.rotate-tile-clockwise");
+pub const ROTATE_TILE_COUNTER_CLOCKWISE: Selector =
+    Selector::new("tile-wave.rotate-tile-counter-clockwise");
+pub const ROTATE_TILE_180: Selector = Selector::new("tile-wave.rotate-tile-180");
+pub const FLIP_TILE_HORIZONTAL: Selector = Selector::new("tile-wave.flip-tile-horizontal");
+pub const FLIP_TILE_VERTICAL: Selector = Selector::new("tile-wave.flip-tile-vertical");
+pub const TRANSPOSE_TILE: Selector = Selector::new("tile-wave.transpose-tile");
+pub const SHIFT_TILE_UP: Selector = Selector::new("tile-wave.shift-tile-up");
+pub const SHIFT_TILE_DOWN: Selector = Selector::new("tile-wave.shift-tile-down");
+pub const SHIFT_TILE_LEFT: Selector = Selector::new("tile-wave.shift-tile-left");
+pub const SHIFT_TILE_RIGHT: Selector = Selector::new("tile-wave.shift-tile-right");
+pub const SHOW_RULES_WINDOW: Selector = Selector::new("tile-wave.show-rules-window");
+pub const VALIDATE_TILESET: Selector = Selector::new("tile-wave.validate-tileset");
+pub const HOVER_MODULE: Selector<Option<usize>> = Selector::new("tile-wave.hover-module");
+pub const SHOW_DIAGNOSTICS_WINDOW: Selector = Selector::new("tile-wave.show-diagnostics-window");
+pub const EXPORT_DIAGNOSTICS: Selector<FileInfo> = Selector::new("tile-wave.export-diagnostics");
+pub const SHOW_BIOMES_WINDOW: Selector = Selector::new("tile-wave.show-biomes-window");
+pub const EXPAND_BIOMES: Selector<FileInfo> = Selector::new("tile-wave.expand-biomes");
+pub const SHOW_BORDER_WINDOW: Selector = Selector::new("tile-wave.show-border-window");
+pub const SHOW_CONSTRAINTS_WINDOW: Selector = Selector::new("tile-wave.show-constraints-window");
+pub const CUT: Selector = Selector::new("tile-wave.cut");
+pub const COPY: Selector = Selector::new("tile-wave.copy");
+pub const PASTE: Selector = Selector::new("tile-wave.paste");
+pub const COPY_MODULE: Selector = Selector::new("tile-wave.copy-module");
+pub const PASTE_AS_MODULE: Selector = Selector::new("tile-wave.paste-as-module");
+pub const SELECT_ALL: Selector = Selector::new("tile-wave.select-all");
+pub const DESELECT: Selector = Selector::new("tile-wave.deselect");
+pub const ROTATE_SELECTION: Selector = Selector::new("tile-wave.rotate-selection");
+pub const FLIP_SELECTION_HORIZONTAL: Selector =
+    Selector::new("tile-wave.flip-selection-horizontal");
+pub const FLIP_SELECTION_VERTICAL: Selector = Selector::new("tile-wave.flip-selection-vertical");
 
 #[derive(Clone, Data, Lens)]
 pub struct AppState {
@@ -26,60 +109,564 @@ pub struct AppState {
     pub modules: Vector<Canvas>,
     pub selected_color: Color,
     pub palette: Vector<Color>,
-    pub map: Vector<Vector<Canvas>>,
+    pub map: Vector<Vector<MapCell>>,
+    pub next_module_id: usize,
+    pub rename_id: Option<usize>,
+    pub rename_text: String,
+    pub tile_dir: Arc<PathBuf>,
+    pub import: ImportState,
+    pub export: ExportState,
+    pub seed: u64,
+    pub status: String,
+    pub map_selection: Option<MapRect>,
+    pub undo_stack: Vector<Snapshot>,
+    pub infinite_map: bool,
+    pub periodic_map: bool,
+    pub tiled_preview: bool,
+    pub show_grid: bool,
+    pub show_rulers: bool,
+    pub world_seed: u64,
+    pub map_view: MapView,
+    pub region: Region,
+    pub editor: EditorView,
+    pub tile_preview: bool,
+    pub preview_neighbours: bool,
+    /// The last copied pixels, used when the system clipboard holds no image.
+    pub clipboard: Option<Canvas>,
+    pub shortcuts: Vector<Shortcut>,
+    pub adjacency: AdjacencyRules,
+    /// The direction shown in the rule editor's matrix.
+    pub rule_direction: usize,
+    /// Global constraints in the text form read by `constraints::parse`.
+    pub constraints: String,
+    pub saved_constraints: String,
+    /// What generation places along the edges of the finite map.
+    pub map_border: MapBorder,
+    /// Which detail tileset each module expands into, read by `biome::parse`.
+    pub biomes: String,
+    pub saved_biomes: String,
+    pub diagnostics: Diagnostics,
+    /// Problems found by the last tileset validation.
+    pub lint: Vector<Finding>,
+    /// The module thumbnail under the mouse, if any. druid has no tooltips at
+    /// this revision, so its findings are shown in the status bar instead.
+    pub hovered_module: Option<usize>,
+    /// A tileset to open once the unsaved-changes prompt is answered.
+    pub pending_tileset: Option<Arc<PathBuf>>,
 }
 
 impl AppState {
-    pub fn new() -> Self {
+    pub fn new(tile_dir: PathBuf) -> Self {
         let mut state = Self {
             active_canvas_id: 0,
             modules: vector![],
             selected_color: Color::WHITE,
             palette: vector![Color::BLACK, Color::WHITE, Color::rgb8(10, 127, 127)],
             map: vector![],
+            next_module_id: 0,
+            rename_id: None,
+            rename_text: String::new(),
+            tile_dir: Arc::new(tile_dir.clone()),
+            import: ImportState::new(),
+            export: ExportState::new(),
+            seed: 0,
+            status: String::new(),
+            map_selection: None,
+            undo_stack: vector![],
+            infinite_map: false,
+            periodic_map: false,
+            tiled_preview: false,
+            show_grid: false,
+            show_rulers: false,
+            world_seed: rand::thread_rng().gen(),
+            map_view: MapView {
+                x: 0.,
+                y: 0.,
+                cell_size: 32.,
+                fit: true,
+            },
+            editor: EditorView {
+                pixel_grid: false,
+                major_grid: false,
+                major_every: 8.,
+                hover: None,
+                mirror: Mirror::Off,
+                wrap: false,
+                tool: Tool::Pencil,
+                selection: None,
+                show_sockets: false,
+            },
+            tile_preview: false,
+            preview_neighbours: false,
+            clipboard: None,
+            shortcuts: shortcuts::load(),
+            adjacency: AdjacencyRules::new(),
+            rule_direction: 0,
+            constraints: String::new(),
+            saved_constraints: String::new(),
+            map_border: MapBorder::new(),
+            biomes: String::new(),
+            saved_biomes: String::new(),
+            diagnostics: Diagnostics::new(),
+            lint: vector![],
+            hovered_module: None,
+            pending_tileset: None,
+            region: Region {
+                x: 0.,
+                y: 0.,
+                width: MAP_SIZE as f64,
+                height: MAP_SIZE as f64,
+            },
         };
 
-        state.load_modules_from_path("tile_images");
+        state.load_modules_from_path(&tile_dir);
 
         state
     }
 
-    pub fn fill_map(&mut self) {
-        let mut rng = rand::thread_rng();
-        let mut map = vector![];
-        for _ in 0..MAP_SIZE {
-            let mut row = vector![];
-            for _ in 0..MAP_SIZE {
-                let index: usize = rng.gen_range(0, self.modules.len());
-                let mut module = self.modules[index].clone();
-                let rotated: bool = rng.gen();
-                let flipped_horizontal: bool = rng.gen();
-                let flipped_vertical: bool = rng.gen();
-                if rotated {
-                    module.rotate_90();
+    /// Runs wave function collapse over the map with a fresh seed. With
+    /// `keep_pinned`, pinned cells are treated as already collapsed and left as they
+    /// are; otherwise the whole map is replaced and all pins are dropped.
+    pub fn generate_map(&mut self, keep_pinned: bool) {
+        if self.map.len() != MAP_SIZE {
+            self.map = (0..MAP_SIZE)
+                .map(|_| (0..MAP_SIZE).map(|_| MapCell::default()).collect())
+                .collect();
+        } else {
+            self.push_undo();
+        }
+        let whole = MapRect {
+            row: 0,
+            col: 0,
+            rows: MAP_SIZE,
+            cols: MAP_SIZE,
+        };
+        self.solve_region(whole, keep_pinned);
+    }
+
+    /// Re-solves the selected cells, constrained by the cells around the selection
+    /// so the new content connects to the rest of the map.
+    pub fn regenerate_selection(&mut self) {
+        let selection = match self.map_selection {
+            Some(selection) => selection,
+            None => return,
+        };
+        self.push_undo();
+        self.solve_region(selection, true);
+    }
+
+    fn solve_region(&mut self, region: MapRect, keep_pinned: bool) {
+        self.seed = rand::thread_rng().gen();
+        let rules = Rules::build(&self.modules, &self.adjacency);
+        self.diagnostics.dead_ends = Arc::new(diagnostics::dead_ends(&rules, &self.modules));
+        let global = match constraints::parse(&self.constraints, &self.modules) {
+            Ok(global) => global,
+            Err(error) => {
+                self.status = format!("Constraints: {}", error);
+                return;
+            }
+        };
+        let ctx = Context::new(
+            &rules,
+            &self.modules,
+            region,
+            self.map.len(),
+            self.map[0].len(),
+            self.periodic_map,
+        );
+        let border = self.map_border.clone();
+        let mut violation = None;
+        let mut contradictions = 0;
+        let mut entropy = vec![];
+        // Measuring entropy walks every cell, so only a few points per attempt are taken.
+        let every = (region.rows * region.cols / (4 * diagnostics::ENTROPY_SAMPLES)).max(1);
+
+        for attempt in 0..MAX_ATTEMPTS {
+            let mut rng = StdRng::seed_from_u64(self.seed.wrapping_add(attempt));
+            let mut wave = Wave::new(&rules, region.rows, region.cols).wrapping(
+                self.periodic_map && region.rows == self.map.len(),
+                self.periodic_map && region.cols == self.map[0].len(),
+            );
+
+            if self
+                .constrain_region(&rules, &mut wave, region, keep_pinned)
+                .is_err()
+            {
+                self.status = "Pinned or surrounding cells cannot all fit together".to_string();
+                return;
+            }
+            if border.prepare(&ctx, &mut wave).is_err() {
+                self.status = "No tiles fit the map border settings".to_string();
+                return;
+            }
+            if global
+                .iter()
+                .any(|constraint| constraint.prepare(&ctx, &mut wave).is_err())
+            {
+                self.status = "Constraints cannot be met in this region".to_string();
+                return;
+            }
+            entropy.clear();
+            let mut step = 0;
+            if wave
+                .collapse_with(&mut rng, |wave| {
+                    if step % every == 0 {
+                        entropy.push(wave.entropy());
+                    }
+                    step += 1;
+                })
+                .is_err()
+            {
+                contradictions += 1;
+                continue;
+            }
+            entropy.push(0.);
+
+            let mut map = self.map.clone();
+            for (row, tiles) in wave.result().into_iter().enumerate() {
+                for (col, tile) in tiles.into_iter().enumerate() {
+                    let cell = &mut map[region.row + row][region.col + col];
+                    if keep_pinned && cell.pinned {
+                        continue;
+                    }
+                    *cell = MapCell {
+                        module_id: rules.tiles[tile].module_id,
+                        transform: rules.tiles[tile].transform,
+                        pinned: false,
+                    };
                 }
-                if flipped_horizontal {
-                    module.flip_horizontal();
+            }
+            let ids: Vec<Vec<usize>> = map
+                .iter()
+                .map(|row| row.iter().map(|cell| cell.module_id).collect())
+                .collect();
+            let violations: Vec<String> = global
+                .iter()
+                .map(|constraint| constraint.as_ref())
+                .chain(Some(&border as &dyn GlobalConstraint))
+                .filter_map(|constraint| constraint.check(&ctx, &ids))
+                .collect();
+            if !violations.is_empty() {
+                violation = Some(violations.join("; "));
+                continue;
+            }
+            let mut usage = BTreeMap::new();
+            for tile in wave.result().into_iter().flatten() {
+                let module_id = rules.tiles[tile].module_id;
+                if let Some(index) = self.find_index(module_id) {
+                    *usage.entry(self.modules[index].name.clone()).or_insert(0) += 1;
                 }
-                if flipped_vertical {
-                    module.flip_vertical();
+            }
+            self.diagnostics.record(Run {
+                seed: self.seed,
+                attempts: attempt + 1,
+                contradictions,
+                succeeded: true,
+                usage: Arc::new(usage),
+                entropy: Arc::new(diagnostics::sample(&entropy)),
+            });
+            self.map = map;
+            self.status = format!(
+                "Generated with seed {} on attempt {}",
+                self.seed,
+                attempt + 1
+            );
+            return;
+        }
+        self.diagnostics.record(Run {
+            seed: self.seed,
+            attempts: MAX_ATTEMPTS,
+            contradictions,
+            succeeded: false,
+            usage: Arc::new(BTreeMap::new()),
+            entropy: Arc::new(diagnostics::sample(&entropy)),
+        });
+        self.status = match violation {
+            Some(violation) => format!(
+                "Generation failed after {} attempts (seed {}): {}",
+                MAX_ATTEMPTS, self.seed, violation
+            ),
+            None => format!(
+                "Generation failed after {} attempts (seed {})",
+                MAX_ATTEMPTS, self.seed
+            ),
+        };
+    }
+
+    /// Seeds `wave` with the pinned cells inside `region` and with what the map
+    /// cells bordering `region` allow next to them.
+    fn constrain_region(
+        &self,
+        rules: &Rules,
+        wave: &mut Wave,
+        region: MapRect,
+        keep_pinned: bool,
+    ) -> Result<(), Contradiction> {
+        for row in 0..region.rows {
+            for col in 0..region.cols {
+                let (map_row, map_col) = (region.row + row, region.col + col);
+                let cell = &self.map[map_row][map_col];
+                if keep_pinned && cell.pinned {
+                    if let Some(tile) = rules.tile_index(cell.module_id, cell.transform) {
+                        wave.set(row, col, tile)?;
+                    }
+                }
+                for (dir, &(dr, dc)) in OFFSETS.iter().enumerate() {
+                    let rows = self.map.len();
+                    let cols = self.map[map_row].len();
+                    let next_row = wrap(map_row as isize + dr, rows, self.periodic_map);
+                    let next_col = wrap(map_col as isize + dc, cols, self.periodic_map);
+                    let (next_row, next_col) = match (next_row, next_col) {
+                        (Some(next_row), Some(next_col)) => (next_row, next_col),
+                        _ => continue,
+                    };
+                    if region.contains(next_row, next_col) {
+                        continue;
+                    }
+                    let next = &self.map[next_row][next_col];
+                    if let Some(tile) = rules.tile_index(next.module_id, next.transform) {
+                        wave.restrict(row, col, rules.neighbours(opposite(dir), tile))?;
+                    }
                 }
-                row.push_back(module);
             }
-            map.push_back(row);
         }
-        self.map = map;
+        Ok(())
+    }
+
+    pub fn new_world(&mut self) {
+        self.world_seed = rand::thread_rng().gen();
+        self.status = format!("World seed {}", self.world_seed);
+    }
+
+    /// Suggests a region to export: the selection or whole map when the map is
+    /// finite, the area at the top-left of the view when it is infinite.
+    pub fn begin_region_export(&mut self) {
+        let (x, y, width, height) = if self.infinite_map {
+            (self.map_view.x.floor(), self.map_view.y.floor(), 32., 32.)
+        } else if let Some(selection) = self.map_selection {
+            (
+                selection.col as f64,
+                selection.row as f64,
+                selection.cols as f64,
+                selection.rows as f64,
+            )
+        } else {
+            (0., 0., MAP_SIZE as f64, MAP_SIZE as f64)
+        };
+        self.region = Region {
+            x,
+            y,
+            width,
+            height,
+        };
     }
 
-    pub fn load_modules_from_path(&mut self, path: &str) {
-        for entry in fs::read_dir(path).unwrap() {
-            let entry = entry.unwrap();
-            let path = entry.path();
-            let canvas = Canvas::new_from_image(&path, self.next_id());
-            self.modules.push_back(canvas)
+    pub fn export_region(&mut self, path: &Path) {
+        let (x, y) = (self.region.x as i64, self.region.y as i64);
+        let width = self.region.width.max(1.) as usize;
+        let height = self.region.height.max(1.) as usize;
+
+        let image = if self.infinite_map {
+            let rules = Rules::build(&self.modules, &self.adjacency);
+            World::new(rules, self.world_seed).render_region(x, y, width, height)
+        } else {
+            let mut cells = vec![];
+            for row in y..y + height as i64 {
+                for col in x..x + width as i64 {
+                    cells.push(self.map_cell_canvas(row, col));
+                }
+            }
+            compose(width, height, &cells)
+        };
+        self.status = match image.save(path) {
+            Ok(()) => format!("Exported the region to {}", path.display()),
+            Err(error) => format!("Could not export the region: {}", error),
+        };
+    }
+
+    fn map_cell_canvas(&self, row: i64, col: i64) -> Option<Canvas> {
+        if row < 0 || col < 0 {
+            return None;
         }
-        if self.modules.len() == 0 {
-            self.modules.push_back(Canvas::new(self.next_id()));
+        let cell = self.map.get(row as usize)?.get(col as usize)?;
+        let index = self.find_index(cell.module_id)?;
+        Some(cell.transform.apply(&self.modules[index]))
+    }
+
+    /// Remembers the map before a map edit.
+    pub fn push_undo(&mut self) {
+        self.push_snapshot(Snapshot::Map(self.map.clone()));
+    }
+
+    /// Remembers the active module before an edit to its pixels.
+    pub fn push_module_undo(&mut self) {
+        self.push_snapshot(Snapshot::Module(Arc::new(self.get_active_module().clone())));
+    }
+
+    fn push_snapshot(&mut self, snapshot: Snapshot) {
+        self.undo_stack.push_back(snapshot);
+        if self.undo_stack.len() > UNDO_LIMIT {
+            self.undo_stack.pop_front();
+        }
+    }
+
+    pub fn undo(&mut self) {
+        match self.undo_stack.pop_back() {
+            Some(Snapshot::Map(map)) => self.map = map,
+            Some(Snapshot::Module(before)) => {
+                // Only the pixels go back; a rename or save since still stands, and a
+                // module deleted since has nothing to go back to.
+                if let Some(index) = self.find_index(before.id) {
+                    let module = &mut self.modules[index];
+                    module.width = before.width;
+                    module.height = before.height;
+                    module.storage = before.storage.clone();
+                    module.sockets = before.sockets.clone();
+                    module.dirty = true;
+                    self.active_canvas_id = before.id;
+                    self.editor.selection = None;
+                }
+            }
+            Some(Snapshot::Added(id)) => {
+                // Once saved, the module's file would bring it back on the next launch.
+                let index = self.find_index(id).filter(|&index| {
+                    self.modules.len() > 1 && !self.modules[index].file_path().exists()
+                });
+                if let Some(index) = index {
+                    self.modules.remove(index);
+                    if self.active_canvas_id == id {
+                        let index = index.min(self.modules.len() - 1);
+                        self.active_canvas_id = self.modules[index].id;
+                    }
+                }
+            }
+            None => {}
+        }
+    }
+
+    /// Places the active module into a map cell, keeping the cell's transform if it
+    /// already held that module.
+    pub fn paint_map_cell(&mut self, row: usize, col: usize) {
+        let module_id = self.active_canvas_id;
+        let cell = &mut self.map[row][col];
+        if cell.module_id != module_id {
+            *cell = MapCell {
+                module_id,
+                transform: Transform::IDENTITY,
+                pinned: true,
+            };
+        }
+        cell.pinned = true;
+    }
+
+    pub fn rotate_map_cell(&mut self, row: usize, col: usize) {
+        let cell = &mut self.map[row][col];
+        cell.transform = cell.transform.then(Transform {
+            rotation: 1,
+            flip: false,
+        });
+        cell.pinned = true;
+    }
+
+    pub fn flip_map_cell(&mut self, row: usize, col: usize) {
+        let cell = &mut self.map[row][col];
+        cell.transform = cell.transform.then(Transform {
+            rotation: 0,
+            flip: true,
+        });
+        cell.pinned = true;
+    }
+
+    pub fn toggle_pin(&mut self, row: usize, col: usize) {
+        let cell = &mut self.map[row][col];
+        cell.pinned = !cell.pinned;
+    }
+
+    pub fn pick_map_cell(&mut self, row: usize, col: usize) {
+        self.pick_module(self.map[row][col].module_id);
+    }
+
+    pub fn pick_module(&mut self, module_id: usize) {
+        if self.find_index(module_id).is_some() {
+            self.active_canvas_id = module_id;
+            self.get_active_module_mut().current_color = self.selected_color.clone();
+        }
+    }
+
+    /// Replaces the current modules with the tileset in `path`, discarding unsaved
+    /// changes, and clears everything that refers to the old modules.
+    pub fn open_tileset(&mut self, path: PathBuf) {
+        self.modules.clear();
+        self.map = vector![];
+        self.undo_stack = vector![];
+        self.map_selection = None;
+        self.rename_id = None;
+        self.lint = vector![];
+        self.hovered_module = None;
+        self.diagnostics = Diagnostics::new();
+        self.tile_dir = Arc::new(path.clone());
+        self.load_modules_from_path(&path);
+        self.active_canvas_id = self.modules[0].id;
+    }
+
+    /// Loads every image under `path` in natural filename order. Images in
+    /// subdirectories are loaded after the ones beside them and grouped by their
+    /// relative directory.
+    pub fn load_modules_from_path(&mut self, path: &Path) {
+        self.adjacency = AdjacencyRules::load(path);
+        self.constraints = constraints::load(path);
+        self.saved_constraints = self.constraints.clone();
+        self.biomes = biome::load(path);
+        self.saved_biomes = self.biomes.clone();
+        let (modules, skipped) = load_tileset(path, self.next_module_id);
+        if !skipped.is_empty() {
+            self.status = format!(
+                "Skipped {} image(s) of another size than the tiles: {}",
+                skipped.len(),
+                skipped
+                    .iter()
+                    .map(|path| file_name(path))
+                    .collect::<Vec<String>>()
+                    .join(", ")
+            );
+        }
+        if let Some(last) = modules.iter().map(|module| module.id).max() {
+            self.next_module_id = last + 1;
+        }
+        self.modules.append(modules);
+        if self.modules.is_empty() {
+            // A blank placeholder is nothing to save until it is drawn on.
+            let mut canvas = self.new_module();
+            canvas.dirty = false;
+            self.modules.push_back(canvas);
+        }
+    }
+
+    pub fn begin_import(&mut self, path: PathBuf) {
+        let active = self.get_active_module();
+        let (width, height) = (active.width, active.height);
+        self.import.open(path, width, height);
+    }
+
+    /// Adds the tiles of the sliced atlas as new, unsaved modules in the active group.
+    pub fn apply_import(&mut self) {
+        let stem = self.import.stem();
+        let group = self.get_active_module().group.clone();
+        let mut first_id = None;
+        for tile in self.import.slice().into_iter().filter(|tile| !tile.skipped) {
+            let image = DynamicImage::ImageRgba8(tile.image).into_rgb8();
+            let id = self.alloc_id();
+            let mut canvas = Canvas::from_image(id, &image);
+            canvas.name = self.unique_name(&format!("{}_{}", stem, id));
+            canvas.group = group.clone();
+            canvas.current_color = self.selected_color.clone();
+            let dir = self.tile_dir.join(&canvas.group);
+            canvas.path = Some(Arc::new(dir.join(format!("{}.png", canvas.name))));
+            first_id.get_or_insert(id);
+            self.modules.push_back(canvas);
+        }
+        if let Some(id) = first_id {
+            self.active_canvas_id = id;
         }
     }
 
@@ -88,34 +675,313 @@ impl AppState {
         data.get_active_module_mut().current_color = color.clone();
     }
 
+    /// Makes the module `step` places along the strip active, wrapping around.
+    pub fn step_module(&mut self, step: isize) {
+        let index = match self.find_index(self.active_canvas_id) {
+            Some(index) => index as isize,
+            None => return,
+        };
+        let len = self.modules.len() as isize;
+        let next = (index + step).rem_euclid(len) as usize;
+        self.active_canvas_id = self.modules[next].id;
+        self.get_active_module_mut().current_color = self.selected_color.clone();
+        self.editor.selection = None;
+    }
+
+    /// Applies `op` to the whole active module, which can be undone.
+    pub fn transform_active(&mut self, op: impl FnOnce(&mut Canvas)) {
+        self.push_module_undo();
+        let canvas = self.get_active_module_mut();
+        op(canvas);
+        canvas.dirty = true;
+    }
+
+    pub fn select_palette_color(&mut self, slot: usize) {
+        if let Some(color) = self.palette.get(slot).cloned() {
+            self.selected_color = color.clone();
+            self.get_active_module_mut().current_color = color;
+        }
+    }
+
     pub fn click_module(_ctx: &mut EventCtx, (data, module): &mut (Self, Canvas), _env: &Env) {
         data.active_canvas_id = module.id;
         data.get_active_module_mut().current_color = data.selected_color.clone();
     }
 
-    pub fn next_id(&self) -> usize {
-        if let Some(module) = self.modules.last() {
-            module.id + 1
-        } else {
-            0
-        }
+    /// Hands out module ids. Ids are never reused, even after a module is deleted.
+    pub fn alloc_id(&mut self) -> usize {
+        let id = self.next_module_id;
+        self.next_module_id += 1;
+        id
     }
 
-    pub fn click_add_module(_ctx: &mut EventCtx, data: &mut Self, _env: &Env) {
-        let next_id = data.next_id();
-        let mut canvas = Canvas::new(next_id);
-        canvas.current_color = data.selected_color.clone();
-        data.modules.push_back(canvas);
-        data.active_canvas_id = next_id;
+    /// A blank module that will be saved into the active module's group, sized like
+    /// the active module.
+    pub fn new_module(&mut self) -> Canvas {
+        let id = self.alloc_id();
+        let mut canvas = match self.find_index(self.active_canvas_id) {
+            Some(index) => {
+                let active = &self.modules[index];
+                let mut canvas = Canvas::with_size(id, active.width, active.height);
+                canvas.group = active.group.clone();
+                canvas
+            }
+            None => Canvas::new(id),
+        };
+        canvas.name = self.unique_name(&canvas.name);
+        canvas.current_color = self.selected_color.clone();
+        let dir = self.tile_dir.join(&canvas.group);
+        canvas.path = Some(Arc::new(dir.join(format!("{}.png", canvas.name))));
+        canvas
+    }
+
+    pub fn click_add_module(ctx: &mut EventCtx, _data: &mut Self, _env: &Env) {
+        ctx.submit_command(NEW_MODULE);
+    }
+
+    pub fn find_index(&self, id: usize) -> Option<usize> {
+        self.modules.iter().position(|module| module.id == id)
     }
 
     pub fn get_index_from_id(&self, id: usize) -> usize {
-        for (index, module) in self.modules.iter().enumerate() {
-            if module.id == id {
-                return index;
+        self.find_index(id).expect("No module found with that ID")
+    }
+
+    pub fn unique_name(&self, base: &str) -> String {
+        let taken = |name: &str| self.modules.iter().any(|module| module.name == name);
+        if !taken(base) {
+            return base.to_string();
+        }
+        let mut n = 2;
+        loop {
+            let name = format!("{}_{}", base, n);
+            if !taken(&name) {
+                return name;
+            }
+            n += 1;
+        }
+    }
+
+    pub fn delete_module(&mut self, id: usize) {
+        let index = match self.find_index(id) {
+            Some(index) => index,
+            None => return,
+        };
+        if let Err(error) = self.modules[index].delete_image() {
+            self.status = format!("Could not delete the module: {}", error);
+            return;
+        }
+        let module = self.modules.remove(index);
+        self.adjacency.remove(&module.key());
+        if let Err(error) = socket::save(&self.tile_dir, &self.modules) {
+            self.status = format!("Could not save the sockets: {}", error);
+        }
+
+        if self.modules.is_empty() {
+            let mut canvas = self.new_module();
+            canvas.dirty = false;
+            self.modules.push_back(canvas);
+        }
+        if self.active_canvas_id == id {
+            let index = index.min(self.modules.len() - 1);
+            self.active_canvas_id = self.modules[index].id;
+        }
+    }
+
+    pub fn duplicate_module(&mut self, id: usize) {
+        let index = match self.find_index(id) {
+            Some(index) => index,
+            None => return,
+        };
+        let mut copy = self.modules[index].clone();
+        copy.id = self.alloc_id();
+        copy.name = self.unique_name(&format!("{}_copy", copy.name));
+        copy.drawing = false;
+        let path = copy
+            .file_path()
+            .with_file_name(format!("{}.png", copy.name));
+        copy.path = Some(Arc::new(path));
+        if let Err(error) = copy.save_as_image() {
+            self.status = format!("Could not save the copy: {}", error);
+        }
+        self.active_canvas_id = copy.id;
+        self.modules.insert(index + 1, copy);
+    }
+
+    pub fn move_module(&mut self, id: usize, offset: isize) {
+        let index = match self.find_index(id) {
+            Some(index) => index,
+            None => return,
+        };
+        let target = index as isize + offset;
+        if target < 0 || target >= self.modules.len() as isize {
+            return;
+        }
+        let module = self.modules.remove(index);
+        self.modules.insert(target as usize, module);
+    }
+
+    pub fn begin_rename(&mut self, id: usize) {
+        if let Some(index) = self.find_index(id) {
+            self.rename_id = Some(id);
+            self.rename_text = self.modules[index].name.clone();
+        }
+    }
+
+    pub fn apply_rename(&mut self) {
+        let id = match self.rename_id.take() {
+            Some(id) => id,
+            None => return,
+        };
+        let index = match self.find_index(id) {
+            Some(index) => index,
+            None => return,
+        };
+        let name = self.rename_text.trim().to_string();
+        if name.is_empty() || name == self.modules[index].name {
+            return;
+        }
+        if let Err(error) = check_module_name(&name) {
+            self.status = error;
+            return;
+        }
+        let name = self.unique_name(&name);
+        let old_key = self.modules[index].key();
+        if let Err(error) = self.modules[index].rename(name) {
+            self.status = format!("Could not rename the module: {}", error);
+            return;
+        }
+        self.adjacency.rename(&old_key, &self.modules[index].key());
+        if let Err(error) = socket::save(&self.tile_dir, &self.modules) {
+            self.status = format!("Could not save the sockets: {}", error);
+        }
+    }
+
+    /// The editor selection, if it still fits the active module.
+    pub fn selection(&self) -> Option<PixelRect> {
+        let canvas = self.get_active_module();
+        self.editor
+            .selection
+            .filter(|rect| rect.fits(canvas.width, canvas.height))
+    }
+
+    pub fn select_all(&mut self) {
+        let canvas = self.get_active_module();
+        self.editor.selection = Some(PixelRect {
+            x: 0,
+            y: 0,
+            width: canvas.width,
+            height: canvas.height,
+        });
+    }
+
+    /// Copies the selected pixels, or the whole active module when nothing is
+    /// selected.
+    pub fn copy_selection(&mut self) -> Canvas {
+        let canvas = self.get_active_module();
+        let piece = match self.selection() {
+            Some(rect) => canvas.crop(rect),
+            None => canvas.clone(),
+        };
+        self.clipboard = Some(piece.clone());
+        piece
+    }
+
+    pub fn cut_selection(&mut self) -> Canvas {
+        self.push_module_undo();
+        let piece = self.copy_selection();
+        let selection = self.selection();
+        let canvas = self.get_active_module_mut();
+        match selection {
+            Some(rect) => canvas.fill_rect(rect, &Color::BLACK),
+            None => canvas.clear(),
+        }
+        piece
+    }
+
+    pub fn copy_module(&mut self) -> Canvas {
+        let module = self.get_active_module().clone();
+        self.clipboard = Some(module.clone());
+        module
+    }
+
+    /// Pastes `piece` at the selection's top-left corner, or the module's, and
+    /// selects what was pasted.
+    pub fn paste(&mut self, piece: &Canvas) {
+        self.push_module_undo();
+        let (x, y) = self
+            .selection()
+            .map(|rect| (rect.x, rect.y))
+            .unwrap_or((0, 0));
+        let canvas = self.get_active_module_mut();
+        canvas.stamp(piece, x, y);
+        let rect = PixelRect {
+            x,
+            y,
+            width: piece.width.min(canvas.width - x),
+            height: piece.height.min(canvas.height - y),
+        };
+        self.editor.selection = Some(rect);
+    }
+
+    pub fn paste_as_module(&mut self, piece: &Canvas) {
+        let mut canvas = self.new_module();
+        canvas.width = piece.width;
+        canvas.height = piece.height;
+        canvas.storage = piece.storage.clone();
+        canvas.dirty = true;
+        self.push_snapshot(Snapshot::Added(canvas.id));
+        self.active_canvas_id = canvas.id;
+        self.modules.push_back(canvas);
+        self.editor.selection = None;
+    }
+
+    /// Applies `op` to the selected pixels in place. A rotated selection keeps its
+    /// top-left corner unless that would push it off the canvas.
+    pub fn transform_selection(&mut self, op: impl FnOnce(&mut Canvas)) {
+        let rect = match self.selection() {
+            Some(rect) => rect,
+            None => return,
+        };
+        self.push_module_undo();
+        let canvas = self.get_active_module_mut();
+        let mut piece = canvas.crop(rect);
+        op(&mut piece);
+        if piece.width > canvas.width || piece.height > canvas.height {
+            return;
+        }
+        let x = rect.x.min(canvas.width - piece.width);
+        let y = rect.y.min(canvas.height - piece.height);
+        canvas.fill_rect(rect, &Color::BLACK);
+        canvas.stamp(&piece, x, y);
+        self.editor.selection = Some(PixelRect {
+            x,
+            y,
+            width: piece.width,
+            height: piece.height,
+        });
+    }
+
+    /// The hovered pixel's coordinates and colour, for the editor's status bar.
+    pub fn hover_text(&self) -> String {
+        if let Some(index) = self.hovered_module.and_then(|id| self.find_index(id)) {
+            let module = &self.modules[index];
+            let messages = self.lint_messages(module.id);
+            if !messages.is_empty() {
+                return format!("{}: {}", module.name, messages.join("; "));
             }
         }
-        panic!("No module found with that ID");
+        let canvas = self.get_active_module();
+        match self.editor.hover {
+            Some(pos) if pos.row < canvas.width && pos.col < canvas.height => {
+                let (r, g, b, a) = canvas[pos].as_rgba8();
+                format!(
+                    "{}, {}  #{:02x}{:02x}{:02x}{:02x}  {}x{}",
+                    pos.row, pos.col, r, g, b, a, canvas.width, canvas.height
+                )
+            }
+            _ => format!("{}x{}", canvas.width, canvas.height),
+        }
     }
 
     pub fn get_active_module(&self) -> &Canvas {
@@ -128,8 +994,147 @@ impl AppState {
         &mut self.modules[index]
     }
 
-    pub fn save_active_canvas_as_image(&self) {
-        self.get_active_module().save_as_image();
+    pub fn save_active_canvas_as_image(&mut self) {
+        if let Err(error) = self.get_active_module_mut().save_as_image() {
+            self.status = format!("Could not save the module: {}", error);
+        }
+    }
+
+    /// Saves everything with unsaved changes. Returns false, with the reason in
+    /// `status`, if something could not be saved.
+    pub fn save_all(&mut self) -> bool {
+        let mut saved = true;
+        if self.has_unsaved_changes() {
+            if let Err(error) = socket::save(&self.tile_dir, &self.modules) {
+                self.status = format!("Could not save the sockets: {}", error);
+                saved = false;
+            }
+        }
+        for module in self.modules.iter_mut().filter(|module| module.dirty) {
+            if let Err(error) = module.save_as_image() {
+                self.status = format!("Could not save {}: {}", module.name, error);
+                saved = false;
+            }
+        }
+        if self.adjacency.dirty {
+            saved &= self.save_adjacency();
+        }
+        if self.constraints != self.saved_constraints {
+            saved &= self.save_constraints();
+        }
+        if self.biomes != self.saved_biomes {
+            saved &= self.save_biomes();
+        }
+        saved
+    }
+
+    /// Saves the hand-edited rules. Returns false, with the reason in `status`,
+    /// if they could not be saved.
+    pub fn save_adjacency(&mut self) -> bool {
+        match self.adjacency.save(&self.tile_dir) {
+            Ok(()) => true,
+            Err(error) => {
+                self.status = format!("Could not save the rules: {}", error);
+                false
+            }
+        }
+    }
+
+    /// Saves the biome plan. Returns false, with the reason in `status`, if it
+    /// could not be saved.
+    pub fn save_biomes(&mut self) -> bool {
+        match biome::save(&self.tile_dir, &self.biomes) {
+            Ok(()) => {
+                self.saved_biomes = self.biomes.clone();
+                true
+            }
+            Err(error) => {
+                self.status = format!("Could not save the biome plan: {}", error);
+                false
+            }
+        }
+    }
+
+    pub fn validate_tileset(&mut self) {
+        let rules = Rules::build(&self.modules, &self.adjacency);
+        self.lint = lint::validate(&rules, &self.modules);
+        let flagged = self
+            .modules
+            .iter()
+            .filter(|module| !self.lint_messages(module.id).is_empty())
+            .count();
+        self.status = if self.lint.is_empty() {
+            "Tileset looks fine".to_string()
+        } else {
+            format!(
+                "{} problem(s) in {} module(s); hover a highlighted module to list them here",
+                self.lint.len(),
+                flagged
+            )
+        };
+    }
+
+    /// The findings of the last validation that still hold for the module.
+    pub fn lint_messages(&self, module_id: usize) -> Vec<String> {
+        let module = match self.find_index(module_id) {
+            Some(index) => &self.modules[index],
+            None => return vec![],
+        };
+        self.lint
+            .iter()
+            .filter(|finding| finding.applies_to(module))
+            .map(|finding| finding.message.clone())
+            .collect()
+    }
+
+    pub fn export_diagnostics(&mut self, path: &Path) {
+        self.status = match self.diagnostics.save_csv(path) {
+            Ok(()) => format!("Saved diagnostics to {}", path.display()),
+            Err(error) => format!("Could not save the diagnostics: {}", error),
+        };
+    }
+
+    /// Expands the map, read as biomes, into a detail map saved as an image.
+    pub fn expand_biomes(&mut self, path: &Path) {
+        let expanded = biome::parse(&self.biomes, &self.tile_dir)
+            .and_then(|plan| biome::expand(&self.map, &self.modules, &plan, self.seed));
+        self.status = match expanded {
+            Ok(image) => match image.save(path) {
+                Ok(()) => format!("Expanded biomes into {}", path.display()),
+                Err(error) => format!("Could not save the detail map: {}", error),
+            },
+            Err(error) => format!("Biome expansion failed: {}", error),
+        };
+    }
+
+    /// Saves the constraint text. Returns false, with the reason in `status`, if
+    /// it could not be saved.
+    pub fn save_constraints(&mut self) -> bool {
+        match constraints::save(&self.tile_dir, &self.constraints) {
+            Ok(()) => {
+                self.saved_constraints = self.constraints.clone();
+                true
+            }
+            Err(error) => {
+                self.status = format!("Could not save the constraints: {}", error);
+                false
+            }
+        }
+    }
+
+    /// Why the constraint text cannot be read, if it cannot.
+    pub fn constraint_error(&self) -> String {
+        match constraints::parse(&self.constraints, &self.modules) {
+            Ok(_) => String::new(),
+            Err(error) => error,
+        }
+    }
+
+    pub fn has_unsaved_changes(&self) -> bool {
+        self.modules.iter().any(|module| module.dirty)
+            || self.adjacency.dirty
+            || self.constraints != self.saved_constraints
+            || self.biomes != self.saved_biomes
     }
 
     pub fn clear_active_canvas(&mut self) {
@@ -137,8 +1142,326 @@ impl AppState {
     }
 }
 
+/// Why `name` cannot name a module file, if it cannot.
+pub fn check_module_name(name: &str) -> Result<(), String> {
+    if name.starts_with('.') {
+        return Err("Module names cannot start with a dot".to_string());
+    }
+    match name
+        .chars()
+        .find(|&c| c.is_control() || "/\\<>:\"|?*".contains(c))
+    {
+        Some(c) => Err(format!("Module names cannot contain {:?}", c)),
+        None => Ok(()),
+    }
+}
+
+/// Loads the modules of a tileset folder, numbered from `first_id`, along with
+/// the images skipped for not being sized like the tiles.
+pub fn load_tileset(path: &Path, first_id: usize) -> (Vector<Canvas>, Vec<PathBuf>) {
+    let (files, skipped) = tile_images(path);
+    let mut modules: Vector<Canvas> = files
+        .into_iter()
+        .enumerate()
+        .filter_map(|(i, (group, path))| {
+            let mut canvas = Canvas::new_from_image(&path, first_id + i)?;
+            canvas.group = group;
+            Some(canvas)
+        })
+        .collect();
+    socket::load(path, &mut modules);
+    (modules, skipped)
+}
+
+/// The images under `dir` sized like most of them, which is taken as the tile
+/// size, and the paths of the rest, such as atlases kept beside the tiles.
+fn tile_images(dir: &Path) -> (Vec<(String, PathBuf)>, Vec<PathBuf>) {
+    let mut files = vec![];
+    collect_images(dir, "", &mut files);
+    let sizes: Vec<Option<(u32, u32)>> = files
+        .iter()
+        .map(|(_, path)| image::image_dimensions(path).ok())
+        .collect();
+    let mut counts: HashMap<(u32, u32), usize> = HashMap::new();
+    for size in sizes.iter().flatten() {
+        *counts.entry(*size).or_insert(0) += 1;
+    }
+    // The most common size, the first one found on a tie.
+    let mut tile_size = None;
+    for size in sizes.iter().flatten() {
+        if tile_size.is_none_or(|best| counts[size] > counts[&best]) {
+            tile_size = Some(*size);
+        }
+    }
+    let mut tiles = vec![];
+    let mut skipped = vec![];
+    for ((group, path), size) in files.into_iter().zip(sizes) {
+        match size {
+            Some(size) if Some(size) != tile_size => skipped.push(path),
+            _ => tiles.push((group, path)),
+        }
+    }
+    (tiles, skipped)
+}
+
+fn collect_images(dir: &Path, group: &str, out: &mut Vec<(String, PathBuf)>) {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(_) => return,
+    };
+    let mut paths: Vec<PathBuf> = entries.filter_map(|e| e.ok()).map(|e| e.path()).collect();
+    paths.sort_by(|a, b| natural_cmp(&file_name(a), &file_name(b)));
+
+    let (dirs, files): (Vec<PathBuf>, Vec<PathBuf>) = paths.into_iter().partition(|p| p.is_dir());
+    for path in files.into_iter().filter(|p| is_image(p)) {
+        out.push((group.to_string(), path));
+    }
+    for path in dirs {
+        let sub_group = if group.is_empty() {
+            file_name(&path)
+        } else {
+            format!("{}/{}", group, file_name(&path))
+        };
+        collect_images(&path, &sub_group, out);
+    }
+}
+
+fn file_name(path: &Path) -> String {
+    path.file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default()
+}
+
+fn is_image(path: &Path) -> bool {
+    path.extension()
+        .map(|ext| ext.to_string_lossy().to_lowercase())
+        .is_some_and(|ext| IMAGE_EXTENSIONS.contains(&ext.as_str()))
+}
+
+/// Compares strings so that runs of digits are ordered by value, e.g.
+/// `tile2.png` sorts before `tile10.png`.
+pub fn natural_cmp(a: &str, b: &str) -> Ordering {
+    let mut a = a.chars().peekable();
+    let mut b = b.chars().peekable();
+    loop {
+        match (a.peek().copied(), b.peek().copied()) {
+            (None, None) => return Ordering::Equal,
+            (None, Some(_)) => return Ordering::Less,
+            (Some(_), None) => return Ordering::Greater,
+            (Some(x), Some(y)) if x.is_ascii_digit() && y.is_ascii_digit() => {
+                let mut x_digits = String::new();
+                while let Some(c) = a.peek().copied().filter(char::is_ascii_digit) {
+                    x_digits.push(c);
+                    a.next();
+                }
+                let mut y_digits = String::new();
+                while let Some(c) = b.peek().copied().filter(char::is_ascii_digit) {
+                    y_digits.push(c);
+                    b.next();
+                }
+                let x_trimmed = x_digits.trim_start_matches('0');
+                let y_trimmed = y_digits.trim_start_matches('0');
+                let ordering = x_trimmed
+                    .len()
+                    .cmp(&y_trimmed.len())
+                    .then_with(|| x_trimmed.cmp(y_trimmed))
+                    .then_with(|| x_digits.len().cmp(&y_digits.len()));
+                if ordering != Ordering::Equal {
+                    return ordering;
+                }
+            }
+            (Some(x), Some(y)) => {
+                let ordering = x
+                    .to_lowercase()
+                    .cmp(y.to_lowercase())
+                    .then_with(|| x.cmp(&y));
+                if ordering != Ordering::Equal {
+                    return ordering;
+                }
+                a.next();
+                b.next();
+            }
+        }
+    }
+}
+
+/// A placed module in the map. The module is looked up by id when drawing, so edits
+/// to a module show up everywhere it is used. Pinned cells survive regeneration.
+#[derive(Clone, Copy, Data, PartialEq)]
+pub struct MapCell {
+    pub module_id: usize,
+    pub transform: Transform,
+    pub pinned: bool,
+}
+
+impl Default for MapCell {
+    fn default() -> Self {
+        Self {
+            module_id: usize::MAX,
+            transform: Transform::IDENTITY,
+            pinned: false,
+        }
+    }
+}
+
+/// A rectangle of map cells.
+#[derive(Clone, Copy, Data, PartialEq)]
+pub struct MapRect {
+    pub row: usize,
+    pub col: usize,
+    pub rows: usize,
+    pub cols: usize,
+}
+
+impl MapRect {
+    /// The smallest rectangle covering both cells.
+    pub fn from_corners((row0, col0): (usize, usize), (row1, col1): (usize, usize)) -> Self {
+        Self {
+            row: row0.min(row1),
+            col: col0.min(col1),
+            rows: row0.abs_diff(row1) + 1,
+            cols: col0.abs_diff(col1) + 1,
+        }
+    }
+
+    pub fn contains(&self, row: usize, col: usize) -> bool {
+        row >= self.row
+            && row < self.row + self.rows
+            && col >= self.col
+            && col < self.col + self.cols
+    }
+}
+
+/// A rectangle of pixels on a canvas, `x` across and `y` down.
+#[derive(Clone, Copy, Data, PartialEq)]
+pub struct PixelRect {
+    pub x: usize,
+    pub y: usize,
+    pub width: usize,
+    pub height: usize,
+}
+
+impl PixelRect {
+    /// The smallest rectangle covering both pixels.
+    pub fn from_corners(a: CanvasPos, b: CanvasPos) -> Self {
+        Self {
+            x: a.row.min(b.row),
+            y: a.col.min(b.col),
+            width: a.row.abs_diff(b.row) + 1,
+            height: a.col.abs_diff(b.col) + 1,
+        }
+    }
+
+    pub fn contains(&self, pos: CanvasPos) -> bool {
+        pos.row >= self.x
+            && pos.row < self.x + self.width
+            && pos.col >= self.y
+            && pos.col < self.y + self.height
+    }
+
+    pub fn fits(&self, width: usize, height: usize) -> bool {
+        self.x + self.width <= width && self.y + self.height <= height
+    }
+}
+
+/// The part of the map shown in the map window: the cell at the top-left corner and
+/// how many pixels a cell takes up. With `fit`, a finite map is instead scaled to
+/// fill the window.
+#[derive(Clone, Copy, Data, PartialEq)]
+pub struct MapView {
+    pub x: f64,
+    pub y: f64,
+    pub cell_size: f64,
+    pub fit: bool,
+}
+
+/// Overlays for the pixel editor and the pixel under the mouse.
+#[derive(Clone, Data, Lens)]
+pub struct EditorView {
+    pub pixel_grid: bool,
+    pub major_grid: bool,
+    pub major_every: f64,
+    pub hover: Option<CanvasPos>,
+    pub mirror: Mirror,
+    pub wrap: bool,
+    pub tool: Tool,
+    pub selection: Option<PixelRect>,
+    pub show_sockets: bool,
+}
+
+#[derive(Clone, Copy, Data, PartialEq)]
+pub enum Tool {
+    Pencil,
+    /// Drag to select pixels; drag a selection to move them.
+    Select,
+}
+
+/// How a painted pixel is repeated across the canvas.
+#[derive(Clone, Copy, Data, PartialEq)]
+pub enum Mirror {
+    Off,
+    /// Mirrored left to right.
+    Horizontal,
+    /// Mirrored top to bottom.
+    Vertical,
+    Both,
+    /// Repeated at every quarter turn around the centre.
+    Rotational,
+    /// Mirrored across the top-left to bottom-right diagonal.
+    Diagonal,
+}
+
+impl Mirror {
+    /// `pos` and every position it is mirrored to on a `width` by `height` canvas.
+    /// Quarter turns and the diagonal only make sense on square canvases; elsewhere
+    /// they fall back to a half turn and to no mirroring.
+    pub fn positions(self, pos: CanvasPos, width: usize, height: usize) -> Vec<CanvasPos> {
+        let (x, y) = (pos.row, pos.col);
+        let (fx, fy) = (width - 1 - x, height - 1 - y);
+        let square = width == height;
+        let points = match self {
+            Mirror::Off => vec![(x, y)],
+            Mirror::Horizontal => vec![(x, y), (fx, y)],
+            Mirror::Vertical => vec![(x, y), (x, fy)],
+            Mirror::Both => vec![(x, y), (fx, y), (x, fy), (fx, fy)],
+            Mirror::Rotational if square => vec![(x, y), (fy, x), (fx, fy), (y, fx)],
+            Mirror::Rotational => vec![(x, y), (fx, fy)],
+            Mirror::Diagonal if square => vec![(x, y), (y, x)],
+            Mirror::Diagonal => vec![(x, y)],
+        };
+        points
+            .into_iter()
+            .map(|(row, col)| CanvasPos { row, col })
+            .collect()
+    }
+}
+
+/// A rectangle of map cells to export, edited in the region export window.
+#[derive(Clone, Data, Lens)]
+pub struct Region {
+    pub x: f64,
+    pub y: f64,
+    pub width: f64,
+    pub height: f64,
+}
+
+/// The state undo returns to. Map and module edits are undone separately, so
+/// undoing one never reverts the other.
+#[derive(Clone, Data)]
+pub enum Snapshot {
+    /// The whole map before a map edit.
+    Map(Vector<Vector<MapCell>>),
+    /// One module before an edit to its pixels.
+    Module(Arc<Canvas>),
+    /// The id of a module added since.
+    Added(usize),
+}
+
 pub struct CanvasLens;
 
+/// The active module together with the editor overlays, for `PaintCanvas`.
+pub struct EditorLens;
+
 impl Lens<AppState, Canvas> for CanvasLens {
     fn with<V, F: FnOnce(&Canvas) -> V>(&self, data: &AppState, f: F) -> V {
         f(data.get_active_module())
@@ -149,80 +1472,178 @@ impl Lens<AppState, Canvas> for CanvasLens {
     }
 }
 
+impl Lens<AppState, (Canvas, EditorView)> for EditorLens {
+    fn with<V, F: FnOnce(&(Canvas, EditorView)) -> V>(&self, data: &AppState, f: F) -> V {
+        let canvas = CanvasLens.with(data, |canvas| canvas.clone());
+        f(&(canvas, data.editor.clone()))
+    }
+
+    fn with_mut<V, F: FnOnce(&mut (Canvas, EditorView)) -> V>(
+        &self,
+        data: &mut AppState,
+        f: F,
+    ) -> V {
+        let mut pair = (
+            self.with(data, |(canvas, _)| canvas.clone()),
+            data.editor.clone(),
+        );
+        let value = f(&mut pair);
+        let (canvas, editor) = pair;
+        CanvasLens.with_mut(data, |old| {
+            if !old.same(&canvas) {
+                *old = canvas;
+            }
+        });
+        data.editor = editor;
+        value
+    }
+}
+
+/// A module's pixels. Modules need not be square or `CANVAS_SIZE` across: ones
+/// sliced from a sprite sheet take the sheet's tile size, and a quarter turn
+/// swaps `width` and `height`. Pixels are stored a column at a time, so `x`
+/// across and `y` down lands at `x * height + y`.
 #[derive(Clone, Data, PartialEq)]
 pub struct Canvas {
     pub id: usize,
+    pub name: String,
+    pub group: String,
+    pub path: Option<Arc<PathBuf>>,
+    pub dirty: bool,
     pub drawing: bool,
     pub current_color: Color,
+    pub width: usize,
+    pub height: usize,
     pub storage: Vector<Color>,
+    /// Socket labels in up, right, down, left order.
+    pub sockets: [Socket; 4],
 }
 
 impl Canvas {
     pub fn new(id: usize) -> Self {
-        let storage = (0..CANVAS_SIZE * CANVAS_SIZE)
-            .into_iter()
-            .map(|_| Color::BLACK)
-            .collect();
+        Self::with_size(id, CANVAS_SIZE, CANVAS_SIZE)
+    }
+
+    /// A black module `width` pixels across and `height` down.
+    pub fn with_size(id: usize, width: usize, height: usize) -> Self {
+        let storage = (0..width * height).map(|_| Color::BLACK).collect();
         Self {
             id,
+            name: format!("module_{}", id),
+            group: String::new(),
+            path: None,
+            dirty: true,
             drawing: false,
             current_color: Color::WHITE,
+            width,
+            height,
             storage,
+            sockets: [
+                Socket::empty(),
+                Socket::empty(),
+                Socket::empty(),
+                Socket::empty(),
+            ],
         }
     }
 
-    pub fn image_to_storage(img: RgbImage) -> Vector<Color> {
-        let mut storage: [Color; CANVAS_SIZE * CANVAS_SIZE] =
-            [Color::BLACK; CANVAS_SIZE * CANVAS_SIZE];
+    pub fn image_to_storage(img: &RgbImage) -> Vector<Color> {
+        let height = img.height() as usize;
+        let mut storage = vec![Color::BLACK; img.width() as usize * height];
 
         for (x, y, pixel) in img.enumerate_pixels() {
             let pixel = pixel.channels();
             let r = pixel[0];
             let g = pixel[1];
             let b = pixel[2];
-            let color = Color::rgb8(r.clone(), g.clone(), b.clone());
-            storage[(x as usize * CANVAS_SIZE) + y as usize] = color;
+            let color = Color::rgb8(r, g, b);
+            storage[(x as usize * height) + y as usize] = color;
         }
 
-        let storage = Vector::from(storage.as_ref());
+        Vector::from(storage)
+    }
 
-        storage
+    pub fn from_image(id: usize, img: &RgbImage) -> Self {
+        let mut canvas = Self::with_size(id, img.width() as usize, img.height() as usize);
+        canvas.storage = Self::image_to_storage(img);
+        canvas
     }
 
-    pub fn new_from_image(path: &PathBuf, id: usize) -> Self {
-        let img = image::open(path).unwrap().into_rgb8();
+    pub fn new_from_image(path: &PathBuf, id: usize) -> Option<Self> {
+        let img = image::open(path).ok()?.into_rgb8();
+        let mut canvas = Self::from_image(id, &img);
 
-        let mut storage: [Color; CANVAS_SIZE * CANVAS_SIZE] =
-            [Color::BLACK; CANVAS_SIZE * CANVAS_SIZE];
+        canvas.name = path
+            .file_stem()
+            .map(|stem| stem.to_string_lossy().into_owned())
+            .unwrap_or_else(|| format!("module_{}", id));
+        canvas.path = Some(Arc::new(path.clone()));
+        canvas.dirty = false;
 
-        for (x, y, pixel) in img.enumerate_pixels() {
-            let pixel = pixel.channels();
-            let r = pixel[0];
-            let g = pixel[1];
-            let b = pixel[2];
-            let color = Color::rgb8(r.clone(), g.clone(), b.clone());
-            storage[(x as usize * CANVAS_SIZE) + y as usize] = color;
+        Some(canvas)
+    }
+
+    pub fn clear(&mut self) {
+        self.storage = (0..self.width * self.height)
+            .map(|_| Color::BLACK)
+            .collect();
+        self.dirty = true;
+    }
+
+    pub fn paint(&mut self, pos: CanvasPos, color: &Color) {
+        if &self[pos] != color {
+            self[pos] = color.clone();
+            self.dirty = true;
         }
+    }
 
-        let storage = Vector::from(storage.as_ref());
+    /// A new canvas holding the pixels inside `rect`.
+    pub fn crop(&self, rect: PixelRect) -> Canvas {
+        let mut piece = Canvas::with_size(self.id, rect.width, rect.height);
+        for row in 0..rect.width {
+            for col in 0..rect.height {
+                let from = CanvasPos {
+                    row: rect.x + row,
+                    col: rect.y + col,
+                };
+                piece[CanvasPos { row, col }] = self[from].clone();
+            }
+        }
+        piece
+    }
 
-        Self {
-            id,
-            drawing: false,
-            current_color: Color::WHITE,
-            storage,
+    pub fn fill_rect(&mut self, rect: PixelRect, color: &Color) {
+        for row in rect.x..rect.x + rect.width {
+            for col in rect.y..rect.y + rect.height {
+                self.paint(CanvasPos { row, col }, color);
+            }
         }
     }
 
-    pub fn clear(&mut self) {
-        self.storage = (0..CANVAS_SIZE * CANVAS_SIZE)
-            .into_iter()
-            .map(|_| Color::BLACK)
-            .collect();
+    /// Copies `piece` onto the canvas with its top-left corner at (`x`, `y`),
+    /// dropping whatever falls off the edge.
+    pub fn stamp(&mut self, piece: &Canvas, x: usize, y: usize) {
+        let width = piece.width.min(self.width.saturating_sub(x));
+        let height = piece.height.min(self.height.saturating_sub(y));
+        for row in 0..width {
+            for col in 0..height {
+                let to = CanvasPos {
+                    row: x + row,
+                    col: y + col,
+                };
+                self.paint(to, &piece[CanvasPos { row, col }]);
+            }
+        }
+    }
+
+    pub fn paint_mirrored(&mut self, pos: CanvasPos, color: &Color, mirror: Mirror) {
+        for pos in mirror.positions(pos, self.width, self.height) {
+            self.paint(pos, color);
+        }
     }
 
     pub fn as_image(&self) -> RgbImage {
-        let mut imgbuf: RgbImage = ImageBuffer::new(CANVAS_SIZE as u32, CANVAS_SIZE as u32);
+        let mut imgbuf: RgbImage = ImageBuffer::new(self.width as u32, self.height as u32);
 
         for (x, y, pixel) in imgbuf.enumerate_pixels_mut() {
             let pos = CanvasPos {
@@ -238,30 +1659,147 @@ impl Canvas {
         imgbuf
     }
 
-    pub fn save_as_image(&self) {
+    /// The file this module was loaded from, or where it will be saved if it is new.
+    pub fn file_path(&self) -> PathBuf {
+        match &self.path {
+            Some(path) => path.as_ref().clone(),
+            None => Path::new(TILE_IMAGES_DIR).join(format!("{}.png", self.name)),
+        }
+    }
+
+    pub fn save_as_image(&mut self) -> io::Result<()> {
         let imgbuf = self.as_image();
+        let path = self.file_path();
+
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        imgbuf
+            .save(&path)
+            .map_err(|error| io::Error::other(error.to_string()))?;
 
-        let path = format!("tile_images/test_{}.png", self.id);
+        self.path = Some(Arc::new(path));
+        self.dirty = false;
+        Ok(())
+    }
+
+    pub fn delete_image(&self) -> io::Result<()> {
+        let path = self.file_path();
+        if path.exists() {
+            fs::remove_file(path)?;
+        }
+        Ok(())
+    }
 
-        imgbuf.save(path).unwrap();
+    /// The name qualified by the module's group, e.g. `water/corner`, which tells
+    /// apart modules of the same name in different folders.
+    pub fn key(&self) -> String {
+        if self.group.is_empty() {
+            self.name.clone()
+        } else {
+            format!("{}/{}", self.group, self.name)
+        }
     }
 
+    /// Renames the module, moving its backing file along with it. Fails rather
+    /// than replace another file of that name.
+    pub fn rename(&mut self, name: String) -> io::Result<()> {
+        let old_path = self.file_path();
+        let extension = old_path
+            .extension()
+            .map(|ext| ext.to_string_lossy().into_owned())
+            .unwrap_or_else(|| "png".to_string());
+        let new_path = old_path.with_file_name(format!("{}.{}", name, extension));
+
+        if old_path.exists() {
+            if new_path.exists() {
+                return Err(io::Error::new(
+                    io::ErrorKind::AlreadyExists,
+                    format!("{} already exists", new_path.display()),
+                ));
+            }
+            fs::rename(&old_path, &new_path)?;
+        }
+        if self.path.is_some() {
+            self.path = Some(Arc::new(new_path));
+        }
+        self.name = name;
+        Ok(())
+    }
+
+    /// Rebuilds the canvas as `width` by `height`, taking each pixel from the
+    /// position `source` gives for it. Colours, alpha included, are copied as-is.
+    pub fn remap(
+        &mut self,
+        width: usize,
+        height: usize,
+        source: impl Fn(usize, usize) -> (usize, usize),
+    ) {
+        let mut storage = Vector::new();
+        for row in 0..width {
+            for col in 0..height {
+                let (row, col) = source(row, col);
+                storage.push_back(self[CanvasPos { row, col }].clone());
+            }
+        }
+        self.storage = storage;
+        self.width = width;
+        self.height = height;
+    }
+
+    /// Rotates a quarter turn clockwise.
     pub fn rotate_90(&mut self) {
-        let mut img = self.as_image();
-        let rotated = imageops::rotate90(&mut img);
-        self.storage = Self::image_to_storage(rotated);
+        self.transform(Transform {
+            rotation: 1,
+            flip: false,
+        });
+    }
+
+    pub fn rotate_counter_clockwise(&mut self) {
+        self.transform(Transform {
+            rotation: 3,
+            flip: false,
+        });
+    }
+
+    pub fn rotate_180(&mut self) {
+        self.transform(Transform {
+            rotation: 2,
+            flip: false,
+        });
+    }
+
+    /// Mirrors the canvas across its top-left to bottom-right diagonal.
+    pub fn transpose(&mut self) {
+        self.transform(Transform {
+            rotation: 3,
+            flip: true,
+        });
+    }
+
+    /// Scrolls the pixels `dx` right and `dy` down, wrapping around the edges.
+    pub fn shift(&mut self, dx: isize, dy: isize) {
+        let (w, h) = (self.width as isize, self.height as isize);
+        self.remap(self.width, self.height, |x, y| {
+            (
+                (x as isize - dx).rem_euclid(w) as usize,
+                (y as isize - dy).rem_euclid(h) as usize,
+            )
+        });
     }
 
     pub fn flip_horizontal(&mut self) {
-        let mut img = self.as_image();
-        let flipped = imageops::flip_horizontal(&mut img);
-        self.storage = Self::image_to_storage(flipped);
+        self.transform(Transform {
+            rotation: 0,
+            flip: true,
+        });
     }
 
     pub fn flip_vertical(&mut self) {
-        let mut img = self.as_image();
-        let flipped = imageops::flip_vertical(&mut img);
-        self.storage = Self::image_to_storage(flipped);
+        self.transform(Transform {
+            rotation: 2,
+            flip: true,
+        });
     }
 }
 
@@ -274,14 +1812,79 @@ pub struct CanvasPos {
 impl Index<CanvasPos> for Canvas {
     type Output = Color;
     fn index(&self, pos: CanvasPos) -> &Self::Output {
-        let idx = pos.row * CANVAS_SIZE + pos.col;
+        let idx = pos.row * self.height + pos.col;
         self.storage.index(idx)
     }
 }
 
 impl IndexMut<CanvasPos> for Canvas {
     fn index_mut(&mut self, pos: CanvasPos) -> &mut Self::Output {
-        let idx = pos.row * CANVAS_SIZE + pos.col;
+        let idx = pos.row * self.height + pos.col;
         self.storage.index_mut(idx)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sorted(names: &[&str]) -> Vec<String> {
+        let mut names: Vec<String> = names.iter().map(|name| name.to_string()).collect();
+        names.sort_by(|a, b| natural_cmp(a, b));
+        names
+    }
+
+    #[test]
+    fn numbers_sort_by_value() {
+        assert_eq!(
+            sorted(&["tile10", "tile2", "tile1", "tile_1"]),
+            ["tile1", "tile2", "tile10", "tile_1"]
+        );
+        assert_eq!(
+            sorted(&["b2c10", "b2c9", "b10c1"]),
+            ["b2c9", "b2c10", "b10c1"]
+        );
+    }
+
+    #[test]
+    fn leading_zeros_only_break_ties() {
+        assert_eq!(natural_cmp("tile007", "tile7"), Ordering::Greater);
+        assert_eq!(natural_cmp("tile007", "tile8"), Ordering::Less);
+        assert_eq!(natural_cmp("tile0", "tile00"), Ordering::Less);
+    }
+
+    #[test]
+    fn letters_ignore_case_first() {
+        assert_eq!(sorted(&["b", "A", "a", "B"]), ["A", "a", "B", "b"]);
+        assert_eq!(natural_cmp("Grass", "grass"), Ordering::Less);
+        assert_eq!(natural_cmp("grass", "grass"), Ordering::Equal);
+    }
+
+    #[test]
+    fn canvases_keep_any_size() {
+        let mut canvas = Canvas::with_size(0, 3, 2);
+        assert_eq!(canvas.storage.len(), 6);
+        let red = Color::rgb8(255, 0, 0);
+        canvas.paint(CanvasPos { row: 2, col: 0 }, &red);
+        assert!(canvas.storage[2 * 2] == red);
+
+        let image = canvas.as_image();
+        assert_eq!(image.dimensions(), (3, 2));
+        assert_eq!(image.get_pixel(2, 0).0, [255, 0, 0]);
+        assert_eq!(image.get_pixel(0, 1).0, [0, 0, 0]);
+
+        let loaded = Canvas::from_image(0, &image);
+        assert_eq!((loaded.width, loaded.height), (3, 2));
+        assert!(loaded.storage == canvas.storage);
+    }
+
+    #[test]
+    fn prefixes_come_first() {
+        assert_eq!(natural_cmp("tile", "tile1"), Ordering::Less);
+        assert_eq!(natural_cmp("", "a"), Ordering::Less);
+        assert_eq!(
+            natural_cmp("99999999999999999999999", "100000000000000000000000"),
+            Ordering::Less
+        );
+    }
+}
@@ -1,15 +1,40 @@
-pub struct Delegate;
+pub struct Delegate {
+    main_window: WindowId,
+    /// Set once the user has chosen how to quit, so unsaved changes no longer
+    /// hold the app open.
+    quitting: bool,
+}
+
+use std::sync::Arc;
 
-use druid::{AppDelegate, Command, DelegateCtx, Env, Handled, Target, WindowDesc};
+use druid::{
+    commands, AppDelegate, Command, DelegateCtx, Env, Handled, Target, WindowDesc, WindowId,
+};
 
-use crate::data::{AppState, CLEAR_CANVAS, SAVE_CANVAS, SHOW_MAP_WINDOW};
-use crate::view::map_window;
+use crate::clipboard;
+use crate::data::*;
+use crate::menu::make_menu;
+use crate::shortcuts;
+use crate::view::{
+    biomes_window, border_window, constraints_window, diagnostics_window, export_window,
+    import_window, map_window, region_window, rename_window, rules_window, shortcuts_window,
+    unsaved_changes_window,
+};
+
+impl Delegate {
+    pub fn new(main_window: WindowId) -> Self {
+        Self {
+            main_window,
+            quitting: false,
+        }
+    }
+}
 
 impl AppDelegate<AppState> for Delegate {
     fn command(
         &mut self,
         ctx: &mut DelegateCtx,
-        _target: Target,
+        target: Target,
         cmd: &Command,
         data: &mut AppState,
         _env: &Env,
@@ -23,20 +48,354 @@ impl AppDelegate<AppState> for Delegate {
             //     Handled::Yes
             // }
             _ if cmd.is(SHOW_MAP_WINDOW) => {
-                data.fill_map();
-                let new_win = WindowDesc::new(map_window).window_size((400., 400.));
+                if data.map.is_empty() {
+                    data.generate_map(false);
+                }
+                let new_win = WindowDesc::new(map_window)
+                    .title("Map")
+                    .window_size((560., 680.))
+                    .resizable(true);
+                ctx.new_window(new_win);
+                Handled::Yes
+            }
+            _ if cmd.is(GENERATE_MAP) => {
+                data.generate_map(false);
+                Handled::Yes
+            }
+            _ if cmd.is(REGENERATE_UNPINNED) => {
+                data.generate_map(true);
+                Handled::Yes
+            }
+            _ if cmd.is(REGENERATE_SELECTION) => {
+                data.regenerate_selection();
+                Handled::Yes
+            }
+            _ if cmd.is(NEW_WORLD) => {
+                data.new_world();
+                Handled::Yes
+            }
+            _ if cmd.is(SHOW_REGION_WINDOW) => {
+                data.begin_region_export();
+                let new_win = WindowDesc::new(region_window)
+                    .title("Export Region")
+                    .window_size((320., 180.));
                 ctx.new_window(new_win);
                 Handled::Yes
             }
+            _ if cmd.is(EXPORT_REGION) => {
+                data.export_region(cmd.get_unchecked(EXPORT_REGION).path());
+                Handled::Yes
+            }
+            _ if cmd.is(UNDO) => {
+                data.undo();
+                Handled::Yes
+            }
             _ if cmd.is(SAVE_CANVAS) => {
                 data.save_active_canvas_as_image();
                 Handled::Yes
             }
+            _ if cmd.is(SAVE_ALL) => {
+                data.save_all();
+                Handled::Yes
+            }
+            _ if (cmd.is(commands::QUIT_APP)
+                || cmd.is(commands::CLOSE_WINDOW)
+                    && target == Target::Window(self.main_window))
+                && !self.quitting
+                && data.has_unsaved_changes() =>
+            {
+                data.pending_tileset = None;
+                let new_win = WindowDesc::new(unsaved_changes_window)
+                    .title("Unsaved Changes")
+                    .window_size((360., 110.))
+                    .resizable(false);
+                ctx.new_window(new_win);
+                Handled::Yes
+            }
+            _ if cmd.is(QUIT) => {
+                if *cmd.get_unchecked(QUIT) && !data.save_all() {
+                    return Handled::Yes;
+                }
+                self.quitting = true;
+                ctx.submit_command(commands::QUIT_APP);
+                Handled::Yes
+            }
             _ if cmd.is(CLEAR_CANVAS) => {
                 data.clear_active_canvas();
                 Handled::Yes
             }
+            _ if cmd.is(NEW_MODULE) => {
+                let canvas = data.new_module();
+                data.active_canvas_id = canvas.id;
+                data.modules.push_back(canvas);
+                Handled::Yes
+            }
+            _ if cmd.is(NEXT_MODULE) => {
+                data.step_module(1);
+                Handled::Yes
+            }
+            _ if cmd.is(PREVIOUS_MODULE) => {
+                data.step_module(-1);
+                Handled::Yes
+            }
+            _ if cmd.is(SELECT_PALETTE_COLOR) => {
+                data.select_palette_color(*cmd.get_unchecked(SELECT_PALETTE_COLOR));
+                Handled::Yes
+            }
+            _ if cmd.is(TOGGLE_PIXEL_GRID) => {
+                data.editor.pixel_grid = !data.editor.pixel_grid;
+                ctx.set_menu(make_menu(data), self.main_window);
+                Handled::Yes
+            }
+            _ if cmd.is(TOGGLE_TILE_PREVIEW) => {
+                data.tile_preview = !data.tile_preview;
+                ctx.set_menu(make_menu(data), self.main_window);
+                Handled::Yes
+            }
+            _ if cmd.is(SHOW_RULES_WINDOW) => {
+                let new_win = WindowDesc::new(rules_window)
+                    .title("Adjacency Rules")
+                    .window_size((560., 600.))
+                    .resizable(true);
+                ctx.new_window(new_win);
+                Handled::Yes
+            }
+            _ if cmd.is(VALIDATE_TILESET) => {
+                data.validate_tileset();
+                Handled::Yes
+            }
+            _ if cmd.is(HOVER_MODULE) => {
+                data.hovered_module = *cmd.get_unchecked(HOVER_MODULE);
+                Handled::Yes
+            }
+            _ if cmd.is(SHOW_DIAGNOSTICS_WINDOW) => {
+                let new_win = WindowDesc::new(diagnostics_window)
+                    .title("Diagnostics")
+                    .window_size((480., 600.))
+                    .resizable(true);
+                ctx.new_window(new_win);
+                Handled::Yes
+            }
+            _ if cmd.is(EXPORT_DIAGNOSTICS) => {
+                data.export_diagnostics(cmd.get_unchecked(EXPORT_DIAGNOSTICS).path());
+                Handled::Yes
+            }
+            _ if cmd.is(SHOW_BIOMES_WINDOW) => {
+                let new_win = WindowDesc::new(biomes_window)
+                    .title("Biomes")
+                    .window_size((480., 420.))
+                    .resizable(true);
+                ctx.new_window(new_win);
+                Handled::Yes
+            }
+            _ if cmd.is(EXPAND_BIOMES) => {
+                data.expand_biomes(cmd.get_unchecked(EXPAND_BIOMES).path());
+                Handled::Yes
+            }
+            _ if cmd.is(SHOW_BORDER_WINDOW) => {
+                let new_win = WindowDesc::new(border_window)
+                    .title("Map Border")
+                    .window_size((520., 220.));
+                ctx.new_window(new_win);
+                Handled::Yes
+            }
+            _ if cmd.is(SHOW_CONSTRAINTS_WINDOW) => {
+                let new_win = WindowDesc::new(constraints_window)
+                    .title("Global Constraints")
+                    .window_size((480., 420.))
+                    .resizable(true);
+                ctx.new_window(new_win);
+                Handled::Yes
+            }
+            _ if cmd.is(SHOW_SHORTCUTS_WINDOW) => {
+                let new_win = WindowDesc::new(shortcuts_window)
+                    .title("Keyboard Shortcuts")
+                    .window_size((420., 520.));
+                ctx.new_window(new_win);
+                Handled::Yes
+            }
+            _ if cmd.is(APPLY_SHORTCUTS) => {
+                if let Err(error) = shortcuts::save(&data.shortcuts) {
+                    data.status = format!("Could not save the shortcuts: {}", error);
+                }
+                ctx.set_menu(make_menu(data), self.main_window);
+                Handled::Yes
+            }
+            _ if cmd.is(REBUILD_MENU) => {
+                ctx.set_menu(make_menu(data), self.main_window);
+                Handled::Yes
+            }
+            _ if cmd.is(DELETE_MODULE) => {
+                data.delete_module(*cmd.get_unchecked(DELETE_MODULE));
+                Handled::Yes
+            }
+            _ if cmd.is(DUPLICATE_MODULE) => {
+                data.duplicate_module(*cmd.get_unchecked(DUPLICATE_MODULE));
+                Handled::Yes
+            }
+            _ if cmd.is(MOVE_MODULE_LEFT) => {
+                data.move_module(*cmd.get_unchecked(MOVE_MODULE_LEFT), -1);
+                Handled::Yes
+            }
+            _ if cmd.is(MOVE_MODULE_RIGHT) => {
+                data.move_module(*cmd.get_unchecked(MOVE_MODULE_RIGHT), 1);
+                Handled::Yes
+            }
+            _ if cmd.is(RENAME_MODULE) => {
+                data.begin_rename(*cmd.get_unchecked(RENAME_MODULE));
+                let new_win = WindowDesc::new(rename_window)
+                    .title("Rename Module")
+                    .window_size((260., 80.));
+                ctx.new_window(new_win);
+                Handled::Yes
+            }
+            _ if cmd.is(OPEN_TILESET) => {
+                let path = cmd.get_unchecked(OPEN_TILESET).path().to_path_buf();
+                if data.has_unsaved_changes() {
+                    data.pending_tileset = Some(Arc::new(path));
+                    let new_win = WindowDesc::new(unsaved_changes_window)
+                        .title("Unsaved Changes")
+                        .window_size((360., 110.))
+                        .resizable(false);
+                    ctx.new_window(new_win);
+                } else {
+                    data.open_tileset(path);
+                }
+                Handled::Yes
+            }
+            _ if cmd.is(OPEN_PENDING_TILESET) => {
+                if *cmd.get_unchecked(OPEN_PENDING_TILESET) && !data.save_all() {
+                    return Handled::Yes;
+                }
+                if let Some(path) = data.pending_tileset.take() {
+                    data.open_tileset(path.as_ref().clone());
+                }
+                Handled::Yes
+            }
+            _ if cmd.is(IMPORT_SPRITE_SHEET) => {
+                let path = cmd.get_unchecked(IMPORT_SPRITE_SHEET).path().to_path_buf();
+                data.begin_import(path);
+                let new_win = WindowDesc::new(import_window)
+                    .title("Import Sprite Sheet")
+                    .window_size((480., 520.));
+                ctx.new_window(new_win);
+                Handled::Yes
+            }
+            _ if cmd.is(APPLY_IMPORT) => {
+                data.apply_import();
+                Handled::Yes
+            }
+            _ if cmd.is(SHOW_EXPORT_WINDOW) => {
+                let new_win = WindowDesc::new(export_window)
+                    .title("Export Tileset")
+                    .window_size((320., 160.));
+                ctx.new_window(new_win);
+                Handled::Yes
+            }
+            _ if cmd.is(EXPORT_TILESET) => {
+                let path = cmd.get_unchecked(EXPORT_TILESET).path();
+                data.status = match data.export.export(&data.modules, path) {
+                    Ok(()) => format!("Exported the atlas to {}", path.display()),
+                    Err(error) => format!("Could not export the atlas: {}", error),
+                };
+                Handled::Yes
+            }
+            _ if cmd.is(ROTATE_TILE_CLOCKWISE) => {
+                data.transform_active(Canvas::rotate_90);
+                Handled::Yes
+            }
+            _ if cmd.is(ROTATE_TILE_COUNTER_CLOCKWISE) => {
+                data.transform_active(Canvas::rotate_counter_clockwise);
+                Handled::Yes
+            }
+            _ if cmd.is(ROTATE_TILE_180) => {
+                data.transform_active(Canvas::rotate_180);
+                Handled::Yes
+            }
+            _ if cmd.is(FLIP_TILE_HORIZONTAL) => {
+                data.transform_active(Canvas::flip_horizontal);
+                Handled::Yes
+            }
+            _ if cmd.is(FLIP_TILE_VERTICAL) => {
+                data.transform_active(Canvas::flip_vertical);
+                Handled::Yes
+            }
+            _ if cmd.is(TRANSPOSE_TILE) => {
+                data.transform_active(Canvas::transpose);
+                Handled::Yes
+            }
+            _ if cmd.is(SHIFT_TILE_UP) => {
+                data.transform_active(|canvas| canvas.shift(0, -1));
+                Handled::Yes
+            }
+            _ if cmd.is(SHIFT_TILE_DOWN) => {
+                data.transform_active(|canvas| canvas.shift(0, 1));
+                Handled::Yes
+            }
+            _ if cmd.is(SHIFT_TILE_LEFT) => {
+                data.transform_active(|canvas| canvas.shift(-1, 0));
+                Handled::Yes
+            }
+            _ if cmd.is(SHIFT_TILE_RIGHT) => {
+                data.transform_active(|canvas| canvas.shift(1, 0));
+                Handled::Yes
+            }
+            _ if cmd.is(CUT) => {
+                clipboard::put_image(&data.cut_selection().as_image());
+                Handled::Yes
+            }
+            _ if cmd.is(COPY) => {
+                clipboard::put_image(&data.copy_selection().as_image());
+                Handled::Yes
+            }
+            _ if cmd.is(COPY_MODULE) => {
+                clipboard::put_image(&data.copy_module().as_image());
+                Handled::Yes
+            }
+            _ if cmd.is(PASTE) => {
+                if let Some(piece) = clipboard_contents(data) {
+                    data.paste(&piece);
+                }
+                Handled::Yes
+            }
+            _ if cmd.is(PASTE_AS_MODULE) => {
+                if let Some(piece) = clipboard_contents(data) {
+                    data.paste_as_module(&piece);
+                }
+                Handled::Yes
+            }
+            _ if cmd.is(SELECT_ALL) => {
+                data.select_all();
+                Handled::Yes
+            }
+            _ if cmd.is(DESELECT) => {
+                data.editor.selection = None;
+                Handled::Yes
+            }
+            _ if cmd.is(ROTATE_SELECTION) => {
+                data.transform_selection(Canvas::rotate_90);
+                Handled::Yes
+            }
+            _ if cmd.is(FLIP_SELECTION_HORIZONTAL) => {
+                data.transform_selection(Canvas::flip_horizontal);
+                Handled::Yes
+            }
+            _ if cmd.is(FLIP_SELECTION_VERTICAL) => {
+                data.transform_selection(Canvas::flip_vertical);
+                Handled::Yes
+            }
+            _ if cmd.is(APPLY_RENAME) => {
+                data.apply_rename();
+                Handled::Yes
+            }
             _ => Handled::No,
         }
     }
 }
+
+/// The image on the system clipboard, falling back to the last pixels copied here.
+fn clipboard_contents(data: &AppState) -> Option<Canvas> {
+    clipboard::get_image()
+        .map(|image| Canvas::from_image(0, &image))
+        .or_else(|| data.clipboard.clone())
+}
@@ -0,0 +1,184 @@
+use std::{collections::BTreeMap, fmt::Write, fs, io, path::Path, sync::Arc};
+
+use druid::{
+    im::{vector, Vector},
+    Data,
+};
+
+use crate::{data::Canvas, transform::Transform, wave::Rules};
+
+/// How many generations the diagnostics remember.
+pub const HISTORY: usize = 20;
+/// How many points of each generation's entropy curve are kept.
+pub const ENTROPY_SAMPLES: usize = 20;
+
+const DIRECTIONS: [&str; 4] = ["up", "right", "down", "left"];
+
+/// What happened during one generation.
+#[derive(Clone, Data)]
+pub struct Run {
+    pub seed: u64,
+    pub attempts: u64,
+    /// Attempts that ran into a cell with no possible tile.
+    pub contradictions: u64,
+    pub succeeded: bool,
+    /// Generated cells per module name.
+    pub usage: Arc<BTreeMap<String, usize>>,
+    /// Mean cell entropy from the start to the end of the last attempt.
+    pub entropy: Arc<Vec<f64>>,
+}
+
+impl Run {
+    pub fn contradiction_rate(&self) -> f64 {
+        self.contradictions as f64 / self.attempts.max(1) as f64
+    }
+}
+
+#[derive(Clone, Data)]
+pub struct Diagnostics {
+    /// The most recent generations, oldest first.
+    pub runs: Vector<Run>,
+    /// Modules that could never be placed under the rules of the last generation.
+    pub dead_ends: Arc<Vec<(String, Vec<usize>)>>,
+}
+
+impl Diagnostics {
+    pub fn new() -> Self {
+        Self {
+            runs: vector![],
+            dead_ends: Arc::new(vec![]),
+        }
+    }
+
+    pub fn record(&mut self, run: Run) {
+        self.runs.push_back(run);
+        if self.runs.len() > HISTORY {
+            self.runs.pop_front();
+        }
+    }
+
+    /// Cells per module name summed over the remembered generations.
+    pub fn usage(&self) -> BTreeMap<String, usize> {
+        let mut usage = BTreeMap::new();
+        for run in self.runs.iter() {
+            for (name, count) in run.usage.iter() {
+                *usage.entry(name.clone()).or_insert(0) += count;
+            }
+        }
+        usage
+    }
+
+    /// The entropy curves of the remembered generations, averaged point by point.
+    pub fn mean_entropy(&self) -> Vec<f64> {
+        let curves: Vec<&Arc<Vec<f64>>> = self
+            .runs
+            .iter()
+            .map(|run| &run.entropy)
+            .filter(|curve| !curve.is_empty())
+            .collect();
+        (0..ENTROPY_SAMPLES)
+            .map(|i| {
+                let total: f64 = curves.iter().map(|curve| curve[i]).sum();
+                total / curves.len().max(1) as f64
+            })
+            .collect()
+    }
+
+    /// One row per generation with usage and entropy columns, followed by the
+    /// modules that can never be placed.
+    pub fn save_csv(&self, path: &Path) -> io::Result<()> {
+        let names: Vec<String> = self.usage().into_keys().collect();
+        let mut csv = String::from("seed,attempts,contradictions,contradiction_rate,succeeded");
+        for name in &names {
+            write!(csv, ",{}", quote(&format!("usage:{}", name))).unwrap();
+        }
+        for i in 0..ENTROPY_SAMPLES {
+            write!(csv, ",entropy_{}", i).unwrap();
+        }
+        csv.push('\n');
+        for run in self.runs.iter() {
+            write!(
+                csv,
+                "{},{},{},{:.3},{}",
+                run.seed,
+                run.attempts,
+                run.contradictions,
+                run.contradiction_rate(),
+                run.succeeded
+            )
+            .unwrap();
+            for name in &names {
+                write!(csv, ",{}", run.usage.get(name).unwrap_or(&0)).unwrap();
+            }
+            for i in 0..ENTROPY_SAMPLES {
+                match run.entropy.get(i) {
+                    Some(entropy) => write!(csv, ",{:.4}", entropy).unwrap(),
+                    None => csv.push(','),
+                }
+            }
+            csv.push('\n');
+        }
+        csv.push_str("\nunplaceable_module,missing_directions\n");
+        for (name, dirs) in self.dead_ends.iter() {
+            writeln!(
+                csv,
+                "{},{}",
+                quote(name),
+                directions(dirs).replace(", ", " ")
+            )
+            .unwrap();
+        }
+        fs::write(path, csv)
+    }
+}
+
+/// Quotes a CSV field if it contains a comma, quote or line break.
+fn quote(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+/// Picks `ENTROPY_SAMPLES` evenly spaced points from a curve.
+pub fn sample(curve: &[f64]) -> Vec<f64> {
+    if curve.is_empty() {
+        return vec![];
+    }
+    (0..ENTROPY_SAMPLES)
+        .map(|i| curve[i * (curve.len() - 1) / (ENTROPY_SAMPLES - 1)])
+        .collect()
+}
+
+/// Modules that can never be placed away from the map edge because every one of
+/// their variants has some side nothing may lie against. Each comes with the
+/// sides its best variant is missing.
+pub fn dead_ends(rules: &Rules, modules: &Vector<Canvas>) -> Vec<(String, Vec<usize>)> {
+    modules
+        .iter()
+        .map(|module| (module.name.clone(), missing_sides(rules, module.id)))
+        .filter(|(_, dirs)| !dirs.is_empty())
+        .collect()
+}
+
+/// The sides of the module's best variant that no tile may lie against.
+pub fn missing_sides(rules: &Rules, module_id: usize) -> Vec<usize> {
+    Transform::all()
+        .into_iter()
+        .filter_map(|transform| rules.tile_index(module_id, transform))
+        .map(|tile| {
+            (0..4)
+                .filter(|&dir| !rules.neighbours(dir, tile).iter().any(|&allowed| allowed))
+                .collect::<Vec<usize>>()
+        })
+        .min_by_key(|dirs| dirs.len())
+        .unwrap_or_default()
+}
+
+pub fn directions(dirs: &[usize]) -> String {
+    dirs.iter()
+        .map(|&dir| DIRECTIONS[dir])
+        .collect::<Vec<&str>>()
+        .join(", ")
+}
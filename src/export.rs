@@ -0,0 +1,123 @@
+use std::{fs, io, path::Path};
+
+use druid::{im::Vector, Data, Lens};
+use image::{Rgba, RgbaImage};
+use serde::Serialize;
+
+use crate::{data::Canvas, transform::Transform};
+
+/// Settings for packing the tileset into a single atlas image.
+#[derive(Clone, Data, Lens)]
+pub struct ExportState {
+    pub include_variants: bool,
+    pub padding: f64,
+    pub extrude: f64,
+}
+
+#[derive(Serialize)]
+struct AtlasFile {
+    image: String,
+    width: u32,
+    height: u32,
+    padding: u32,
+    extrude: u32,
+    tiles: Vec<AtlasTile>,
+}
+
+#[derive(Serialize)]
+struct AtlasTile {
+    module_id: usize,
+    name: String,
+    transform: Transform,
+    x: u32,
+    y: u32,
+    width: u32,
+    height: u32,
+}
+
+impl ExportState {
+    pub fn new() -> Self {
+        Self {
+            include_variants: false,
+            padding: 1.,
+            extrude: 0.,
+        }
+    }
+
+    /// Writes the atlas PNG to `path` and a JSON description of it next to it.
+    pub fn export(&self, modules: &Vector<Canvas>, path: &Path) -> io::Result<()> {
+        let padding = self.padding.max(0.) as u32;
+        let extrude = self.extrude.max(0.) as u32;
+
+        let mut tiles = vec![];
+        for module in modules.iter() {
+            if self.include_variants {
+                for (transform, variant) in module.unique_variants() {
+                    tiles.push((module, transform, variant));
+                }
+            } else {
+                tiles.push((module, Transform::IDENTITY, module.clone()));
+            }
+        }
+
+        let cell_width = tiles.iter().map(|(_, _, t)| t.width).max().unwrap_or(0) as u32;
+        let cell_height = tiles.iter().map(|(_, _, t)| t.height).max().unwrap_or(0) as u32;
+        let columns = (tiles.len() as f64).sqrt().ceil().max(1.) as u32;
+        let rows = (tiles.len() as u32).div_ceil(columns);
+        let stride_x = cell_width + 2 * extrude + padding;
+        let stride_y = cell_height + 2 * extrude + padding;
+
+        let mut atlas = RgbaImage::new(columns * stride_x + padding, rows * stride_y + padding);
+        let mut entries = vec![];
+        for (i, (module, transform, tile)) in tiles.into_iter().enumerate() {
+            let i = i as u32;
+            let x = padding + (i % columns) * stride_x + extrude;
+            let y = padding + (i / columns) * stride_y + extrude;
+            blit_extruded(&mut atlas, &tile, x, y, extrude);
+            entries.push(AtlasTile {
+                module_id: module.id,
+                name: module.name.clone(),
+                transform,
+                x,
+                y,
+                width: tile.width as u32,
+                height: tile.height as u32,
+            });
+        }
+
+        atlas
+            .save(path)
+            .map_err(|error| io::Error::other(error.to_string()))?;
+
+        let sidecar = AtlasFile {
+            image: path
+                .file_name()
+                .map(|name| name.to_string_lossy().into_owned())
+                .unwrap_or_default(),
+            width: atlas.width(),
+            height: atlas.height(),
+            padding,
+            extrude,
+            tiles: entries,
+        };
+        let json = serde_json::to_string_pretty(&sidecar)?;
+        fs::write(path.with_extension("json"), json)
+    }
+}
+
+/// Copies `tile` into `atlas` at (`x`, `y`) and repeats its edge pixels `extrude`
+/// times outward so texture filtering never samples a neighbouring tile.
+fn blit_extruded(atlas: &mut RgbaImage, tile: &Canvas, x: u32, y: u32, extrude: u32) {
+    let image = tile.as_image();
+    let (width, height) = (image.width() as i64, image.height() as i64);
+    let extrude = extrude as i64;
+    for dy in -extrude..height + extrude {
+        for dx in -extrude..width + extrude {
+            let sx = dx.max(0).min(width - 1) as u32;
+            let sy = dy.max(0).min(height - 1) as u32;
+            let pixel = image.get_pixel(sx, sy);
+            let rgba = Rgba([pixel[0], pixel[1], pixel[2], 255]);
+            atlas.put_pixel((x as i64 + dx) as u32, (y as i64 + dy) as u32, rgba);
+        }
+    }
+}
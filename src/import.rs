@@ -0,0 +1,110 @@
+use std::{collections::HashSet, path::PathBuf, sync::Arc};
+
+use druid::{Data, Lens};
+use image::{GenericImageView, Rgba, RgbaImage};
+
+use crate::data::CANVAS_SIZE;
+
+/// Settings for slicing a sprite-sheet atlas into modules.
+#[derive(Clone, Data, Lens)]
+pub struct ImportState {
+    pub path: Option<Arc<PathBuf>>,
+    pub atlas: Option<Arc<RgbaImage>>,
+    pub tile_width: f64,
+    pub tile_height: f64,
+    pub margin: f64,
+    pub spacing: f64,
+    pub skip_empty: bool,
+    pub skip_duplicates: bool,
+}
+
+pub struct SlicedTile {
+    pub x: u32,
+    pub y: u32,
+    pub image: RgbaImage,
+    pub skipped: bool,
+}
+
+impl ImportState {
+    pub fn new() -> Self {
+        Self {
+            path: None,
+            atlas: None,
+            tile_width: CANVAS_SIZE as f64,
+            tile_height: CANVAS_SIZE as f64,
+            margin: 0.,
+            spacing: 0.,
+            skip_empty: true,
+            skip_duplicates: true,
+        }
+    }
+
+    pub fn open(&mut self, path: PathBuf, tile_width: usize, tile_height: usize) {
+        self.atlas = image::open(&path)
+            .ok()
+            .map(|img| Arc::new(img.into_rgba8()));
+        self.path = Some(Arc::new(path));
+        self.tile_width = tile_width as f64;
+        self.tile_height = tile_height as f64;
+    }
+
+    /// The atlas file name without its extension, used to name imported modules.
+    pub fn stem(&self) -> String {
+        self.path
+            .as_ref()
+            .and_then(|path| path.file_stem())
+            .map(|stem| stem.to_string_lossy().into_owned())
+            .unwrap_or_else(|| "tile".to_string())
+    }
+
+    /// Cuts the atlas into tiles in row-major order, flagging the ones the current
+    /// settings would leave out.
+    pub fn slice(&self) -> Vec<SlicedTile> {
+        let atlas = match &self.atlas {
+            Some(atlas) => atlas,
+            None => return vec![],
+        };
+        let tile_width = self.tile_width.max(1.) as u32;
+        let tile_height = self.tile_height.max(1.) as u32;
+        let margin = self.margin.max(0.) as u32;
+        let spacing = self.spacing.max(0.) as u32;
+
+        // The colour between tiles, if the sheet leaves any gaps to read it from.
+        let background = if margin > 0 {
+            Some(*atlas.get_pixel(0, 0))
+        } else if spacing > 0 && tile_width < atlas.width() {
+            Some(*atlas.get_pixel(tile_width, 0))
+        } else {
+            None
+        };
+
+        let mut seen = HashSet::new();
+        let mut tiles = vec![];
+        let mut y = margin;
+        while y + tile_height + margin <= atlas.height() {
+            let mut x = margin;
+            while x + tile_width + margin <= atlas.width() {
+                let image = atlas.view(x, y, tile_width, tile_height).to_image();
+                let empty = is_empty(&image, background);
+                let duplicate = !seen.insert(image.as_raw().clone());
+                let skipped =
+                    (self.skip_empty && empty) || (self.skip_duplicates && duplicate && !empty);
+                tiles.push(SlicedTile {
+                    x,
+                    y,
+                    image,
+                    skipped,
+                });
+                x += tile_width + spacing;
+            }
+            y += tile_height + spacing;
+        }
+        tiles
+    }
+}
+
+/// A tile counts as empty when it is fully transparent or entirely the sheet's
+/// background colour.
+fn is_empty(image: &RgbaImage, background: Option<Rgba<u8>>) -> bool {
+    image.pixels().all(|p| p[3] == 0) || background.is_some_and(|b| image.pixels().all(|p| *p == b))
+}
@@ -0,0 +1,133 @@
+use std::collections::HashMap;
+
+use druid::{im::Vector, Data};
+
+use crate::{
+    data::Canvas,
+    diagnostics::{directions, missing_sides},
+    wave::Rules,
+};
+
+/// A problem with one module found by `validate`.
+#[derive(Clone, Data)]
+pub struct Finding {
+    /// The module as it was when validated.
+    pub module: Canvas,
+    pub message: String,
+}
+
+impl Finding {
+    /// Whether the finding still describes `module`, which it no longer does once
+    /// the module's pixels or sockets have been edited.
+    pub fn applies_to(&self, module: &Canvas) -> bool {
+        self.module.id == module.id
+            && self.module.storage.same(&module.storage)
+            && self.module.sockets == module.sockets
+    }
+}
+
+/// Checks the tileset under `rules` for modules that cannot be placed, that only
+/// ever sit next to themselves, that repeat another module, sockets included, up to
+/// rotation and flipping, or that can never touch the rest of the tileset.
+pub fn validate(rules: &Rules, modules: &Vector<Canvas>) -> Vector<Finding> {
+    let mut findings = Vector::new();
+    let mut report = |module: &Canvas, message: String| {
+        findings.push_back(Finding {
+            module: module.clone(),
+            message,
+        })
+    };
+
+    // Which modules may lie next to which, in any direction.
+    let index_of: HashMap<usize, usize> = modules
+        .iter()
+        .enumerate()
+        .map(|(i, module)| (module.id, i))
+        .collect();
+    let mut touches = vec![vec![false; modules.len()]; modules.len()];
+    for (tile, from) in rules.tiles.iter().enumerate() {
+        for dir in 0..4 {
+            for (next, allowed) in rules.neighbours(dir, tile).iter().enumerate() {
+                if let (true, Some(&a), Some(&b)) = (
+                    *allowed,
+                    index_of.get(&from.module_id),
+                    index_of.get(&rules.tiles[next].module_id),
+                ) {
+                    touches[a][b] = true;
+                    touches[b][a] = true;
+                }
+            }
+        }
+    }
+
+    for (i, module) in modules.iter().enumerate() {
+        let missing = missing_sides(rules, module.id);
+        if !missing.is_empty() {
+            report(
+                module,
+                format!("nothing may lie {} of it", directions(&missing)),
+            );
+        }
+        let others = (0..modules.len()).any(|j| j != i && touches[i][j]);
+        if touches[i][i] && !others {
+            report(module, "only ever lies next to itself".to_string());
+        }
+        // Storage alone can match across shapes, so a turned 2x3 module is
+        // only a repeat of one that is 3x2 as well.
+        let variants = module.unique_variants();
+        if let Some(original) = modules.iter().take(i).find(|other| {
+            variants.iter().any(|(_, variant)| {
+                variant.width == other.width
+                    && variant.height == other.height
+                    && variant.storage == other.storage
+                    && variant.sockets == other.sockets
+            })
+        }) {
+            report(
+                module,
+                format!("repeats {} rotated or flipped", original.name),
+            );
+        }
+    }
+
+    // Split the modules into groups that can reach each other through neighbours
+    // and flag every group but the largest.
+    let mut group = vec![usize::MAX; modules.len()];
+    let mut sizes = vec![];
+    for start in 0..modules.len() {
+        if group[start] != usize::MAX {
+            continue;
+        }
+        let id = sizes.len();
+        let mut stack = vec![start];
+        group[start] = id;
+        let mut size = 0;
+        while let Some(i) = stack.pop() {
+            size += 1;
+            for j in 0..modules.len() {
+                if touches[i][j] && group[j] == usize::MAX {
+                    group[j] = id;
+                    stack.push(j);
+                }
+            }
+        }
+        sizes.push(size);
+    }
+    if sizes.len() > 1 {
+        let largest = (0..sizes.len()).max_by_key(|&id| sizes[id]).unwrap();
+        for (i, module) in modules.iter().enumerate() {
+            if group[i] != largest {
+                report(
+                    module,
+                    format!(
+                        "is in a group of {} module(s) that never touches the other {}",
+                        sizes[group[i]],
+                        modules.len() - sizes[group[i]]
+                    ),
+                );
+            }
+        }
+    }
+
+    findings
+}
@@ -1,26 +1,48 @@
 use druid::{AppLauncher, WindowDesc};
 
 mod data;
-use data::{AppState, Canvas};
+use data::AppState;
 
 mod view;
 use view::build_ui;
 
 mod widgets;
 
+mod adjacency;
+mod biome;
+mod border;
+mod clipboard;
+mod constraints;
 mod controllers;
 mod delegate;
+mod diagnostics;
+mod export;
+mod import;
+mod lint;
+mod menu;
+mod shortcuts;
+mod socket;
+mod transform;
+mod wave;
+mod world;
 
 pub fn main() {
+    let tile_dir = std::env::args()
+        .nth(1)
+        .unwrap_or_else(|| data::TILE_IMAGES_DIR.to_string());
+    let initial_state = AppState::new(tile_dir.into());
+
     let main_window = WindowDesc::new(build_ui)
         .title("Tial Wave")
-        .window_size((438.0, 434.0))
-        .resizable(false);
+        .menu(menu::make_menu(&initial_state))
+        .window_size((560.0, 560.0))
+        .with_min_size((320.0, 320.0))
+        .resizable(true);
 
-    let initial_state = AppState::new();
+    let main_window_id = main_window.id;
 
     AppLauncher::with_window(main_window)
-        .delegate(delegate::Delegate)
+        .delegate(delegate::Delegate::new(main_window_id))
         .launch(initial_state)
         .expect("Failed to launch application");
 }
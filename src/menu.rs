@@ -0,0 +1,140 @@
+use druid::{Command, LocalizedString, MenuDesc, MenuItem};
+
+use crate::{
+    controllers::{export_tileset_command, import_sprite_sheet_command, open_tileset_command},
+    data::*,
+    shortcuts::DEFAULT_SHORTCUTS,
+};
+
+/// The main window's menu bar, with hotkeys taken from the current shortcuts.
+pub fn make_menu(data: &AppState) -> MenuDesc<AppState> {
+    let item = |id: &str, command: Command| {
+        let title = DEFAULT_SHORTCUTS
+            .iter()
+            .find(|(shortcut_id, _, _)| *shortcut_id == id)
+            .map(|(_, title, _)| *title)
+            .unwrap();
+        let item = MenuItem::new(LocalizedString::new(title), command);
+        let binding = data
+            .shortcuts
+            .iter()
+            .find(|shortcut| shortcut.id == id)
+            .and_then(|shortcut| shortcut.parse());
+        match binding {
+            Some((mods, key)) => item.hotkey(mods, key),
+            None => item,
+        }
+    };
+
+    let file = MenuDesc::new(LocalizedString::new("File"))
+        .append(item("open-tileset", open_tileset_command()))
+        .append(MenuItem::new(
+            LocalizedString::new("Import Sprite Sheet..."),
+            import_sprite_sheet_command(),
+        ))
+        .append(MenuItem::new(
+            LocalizedString::new("Export Tileset..."),
+            export_tileset_command(),
+        ))
+        .append_separator()
+        .append(item("save", SAVE_CANVAS.into()))
+        .append(item("save-all", SAVE_ALL.into()));
+
+    let edit = MenuDesc::new(LocalizedString::new("Edit"))
+        .append(item("undo", UNDO.into()))
+        .append_separator()
+        .append(item("cut", CUT.into()))
+        .append(item("copy", COPY.into()))
+        .append(item("paste", PASTE.into()))
+        .append(MenuItem::new(
+            LocalizedString::new("Copy Module"),
+            COPY_MODULE,
+        ))
+        .append(item("paste-as-module", PASTE_AS_MODULE.into()))
+        .append_separator()
+        .append(item("select-all", SELECT_ALL.into()))
+        .append(item("deselect", DESELECT.into()))
+        .append_separator()
+        .append(MenuItem::new(
+            LocalizedString::new("Keyboard Shortcuts..."),
+            SHOW_SHORTCUTS_WINDOW,
+        ));
+
+    let mut palette = MenuDesc::new(LocalizedString::new("Palette"));
+    for slot in 0..9 {
+        let id = format!("palette-{}", slot + 1);
+        palette = palette.append(item(&id, SELECT_PALETTE_COLOR.with(slot)));
+    }
+    let tile = MenuDesc::new(LocalizedString::new("Tile"))
+        .append(item("new-module", NEW_MODULE.into()))
+        .append(item("clear", CLEAR_CANVAS.into()))
+        .append_separator()
+        .append(item("rotate-clockwise", ROTATE_TILE_CLOCKWISE.into()))
+        .append(item(
+            "rotate-counter-clockwise",
+            ROTATE_TILE_COUNTER_CLOCKWISE.into(),
+        ))
+        .append(item("rotate-180", ROTATE_TILE_180.into()))
+        .append(item("flip-horizontal", FLIP_TILE_HORIZONTAL.into()))
+        .append(item("flip-vertical", FLIP_TILE_VERTICAL.into()))
+        .append(item("transpose", TRANSPOSE_TILE.into()))
+        .append_separator()
+        .append(item("shift-up", SHIFT_TILE_UP.into()))
+        .append(item("shift-down", SHIFT_TILE_DOWN.into()))
+        .append(item("shift-left", SHIFT_TILE_LEFT.into()))
+        .append(item("shift-right", SHIFT_TILE_RIGHT.into()))
+        .append_separator()
+        .append(item("next-module", NEXT_MODULE.into()))
+        .append(item("previous-module", PREVIOUS_MODULE.into()))
+        .append_separator()
+        .append(palette);
+
+    let map = MenuDesc::new(LocalizedString::new("Map"))
+        .append(item("show-map", SHOW_MAP_WINDOW.into()))
+        .append(item("generate-map", GENERATE_MAP.into()))
+        .append(MenuItem::new(
+            LocalizedString::new("Regenerate Unpinned"),
+            REGENERATE_UNPINNED,
+        ))
+        .append(MenuItem::new(
+            LocalizedString::new("Regenerate Selection"),
+            REGENERATE_SELECTION,
+        ))
+        .append_separator()
+        .append(MenuItem::new(
+            LocalizedString::new("Adjacency Rules..."),
+            SHOW_RULES_WINDOW,
+        ))
+        .append(MenuItem::new(
+            LocalizedString::new("Map Border..."),
+            SHOW_BORDER_WINDOW,
+        ))
+        .append(MenuItem::new(
+            LocalizedString::new("Global Constraints..."),
+            SHOW_CONSTRAINTS_WINDOW,
+        ))
+        .append(MenuItem::new(
+            LocalizedString::new("Biomes..."),
+            SHOW_BIOMES_WINDOW,
+        ))
+        .append_separator()
+        .append(MenuItem::new(
+            LocalizedString::new("Validate Tileset"),
+            VALIDATE_TILESET,
+        ))
+        .append(MenuItem::new(
+            LocalizedString::new("Diagnostics..."),
+            SHOW_DIAGNOSTICS_WINDOW,
+        ));
+
+    let view = MenuDesc::new(LocalizedString::new("View"))
+        .append(item("pixel-grid", TOGGLE_PIXEL_GRID.into()).selected_if(|| data.editor.pixel_grid))
+        .append(item("tile-preview", TOGGLE_TILE_PREVIEW.into()).selected_if(|| data.tile_preview));
+
+    MenuDesc::empty()
+        .append(file)
+        .append(edit)
+        .append(tile)
+        .append(map)
+        .append(view)
+}
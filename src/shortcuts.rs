@@ -0,0 +1,155 @@
+use std::{collections::BTreeMap, fs, io, str::FromStr};
+
+use druid::{im::Vector, Data, KbKey, Lens, RawMods};
+
+/// Where rebound shortcuts are kept, relative to the working directory.
+pub const KEYMAP_FILE: &str = "keymap.json";
+
+/// Every command that can have a shortcut: its id in the keymap file, its menu
+/// title and its default binding. `Cmd` is Command on macOS and Ctrl elsewhere.
+pub const DEFAULT_SHORTCUTS: [(&str, &str, &str); 37] = [
+    ("save", "Save", "Cmd+S"),
+    ("save-all", "Save All", "Cmd+Shift+S"),
+    ("open-tileset", "Open Tileset...", "Cmd+O"),
+    ("undo", "Undo", "Cmd+Z"),
+    ("cut", "Cut", "Cmd+X"),
+    ("copy", "Copy", "Cmd+C"),
+    ("paste", "Paste", "Cmd+V"),
+    ("paste-as-module", "Paste as New Module", "Cmd+Shift+V"),
+    ("select-all", "Select All", "Cmd+A"),
+    ("deselect", "Deselect", "Escape"),
+    ("new-module", "New Module", "Cmd+N"),
+    ("clear", "Clear", "Cmd+Backspace"),
+    ("rotate-clockwise", "Rotate 90° Clockwise", "Cmd+R"),
+    (
+        "rotate-counter-clockwise",
+        "Rotate 90° Counter-clockwise",
+        "Cmd+Shift+R",
+    ),
+    ("rotate-180", "Rotate 180°", ""),
+    ("flip-horizontal", "Flip Horizontally", "Alt+H"),
+    ("flip-vertical", "Flip Vertically", "Alt+V"),
+    ("transpose", "Transpose", ""),
+    ("shift-up", "Shift Up", "Shift+ArrowUp"),
+    ("shift-down", "Shift Down", "Shift+ArrowDown"),
+    ("shift-left", "Shift Left", "Shift+ArrowLeft"),
+    ("shift-right", "Shift Right", "Shift+ArrowRight"),
+    ("next-module", "Next Module", "Cmd+ArrowRight"),
+    ("previous-module", "Previous Module", "Cmd+ArrowLeft"),
+    ("palette-1", "Color 1", "1"),
+    ("palette-2", "Color 2", "2"),
+    ("palette-3", "Color 3", "3"),
+    ("palette-4", "Color 4", "4"),
+    ("palette-5", "Color 5", "5"),
+    ("palette-6", "Color 6", "6"),
+    ("palette-7", "Color 7", "7"),
+    ("palette-8", "Color 8", "8"),
+    ("palette-9", "Color 9", "9"),
+    ("show-map", "Show Map", "Cmd+M"),
+    ("generate-map", "Generate Map", "Cmd+G"),
+    ("pixel-grid", "Pixel Grid", "Cmd+'"),
+    ("tile-preview", "Tiling Preview", "Cmd+P"),
+];
+
+/// A command's key binding as the user typed it, e.g. `Cmd+Shift+S`. An empty
+/// binding leaves the command without a shortcut.
+#[derive(Clone, Data, Lens)]
+pub struct Shortcut {
+    pub id: String,
+    pub title: String,
+    pub binding: String,
+}
+
+impl Shortcut {
+    /// The modifiers and key of the binding, or `None` if it is empty or not
+    /// understood.
+    pub fn parse(&self) -> Option<(RawMods, KbKey)> {
+        let binding = self.binding.trim();
+        if binding.is_empty() {
+            return None;
+        }
+        // Split on the last `+` before the final character, so `Cmd++` binds plus.
+        let last = binding.chars().last().unwrap().len_utf8();
+        let (mods, key) = match binding[..binding.len() - last].rfind('+') {
+            Some(i) => (&binding[..i], &binding[i + 1..]),
+            None => ("", binding),
+        };
+
+        let (mut alt, mut ctrl, mut meta, mut shift) = (false, false, false, false);
+        for name in mods.split('+').filter(|name| !name.is_empty()) {
+            match name.trim().to_lowercase().as_str() {
+                "alt" | "option" => alt = true,
+                "ctrl" | "control" => ctrl = true,
+                "meta" | "super" | "win" => meta = true,
+                "shift" => shift = true,
+                "cmd" | "command" if cfg!(target_os = "macos") => meta = true,
+                "cmd" | "command" => ctrl = true,
+                _ => return None,
+            }
+        }
+        let key = match key.chars().count() {
+            1 => KbKey::Character(key.to_lowercase()),
+            _ => KbKey::from_str(key).ok()?,
+        };
+        Some((raw_mods(alt, ctrl, meta, shift), key))
+    }
+}
+
+fn raw_mods(alt: bool, ctrl: bool, meta: bool, shift: bool) -> RawMods {
+    match (alt, ctrl, meta, shift) {
+        (false, false, false, false) => RawMods::None,
+        (true, false, false, false) => RawMods::Alt,
+        (false, true, false, false) => RawMods::Ctrl,
+        (false, false, true, false) => RawMods::Meta,
+        (false, false, false, true) => RawMods::Shift,
+        (true, true, false, false) => RawMods::AltCtrl,
+        (true, false, true, false) => RawMods::AltMeta,
+        (true, false, false, true) => RawMods::AltShift,
+        (false, true, true, false) => RawMods::CtrlMeta,
+        (false, true, false, true) => RawMods::CtrlShift,
+        (false, false, true, true) => RawMods::MetaShift,
+        (true, true, true, false) => RawMods::AltCtrlMeta,
+        (true, true, false, true) => RawMods::AltCtrlShift,
+        (true, false, true, true) => RawMods::AltMetaShift,
+        (false, true, true, true) => RawMods::CtrlMetaShift,
+        (true, true, true, true) => RawMods::AltCtrlMetaShift,
+    }
+}
+
+pub fn defaults() -> Vector<Shortcut> {
+    DEFAULT_SHORTCUTS
+        .iter()
+        .map(|(id, title, binding)| Shortcut {
+            id: id.to_string(),
+            title: title.to_string(),
+            binding: binding.to_string(),
+        })
+        .collect()
+}
+
+/// The default shortcuts with any bindings from the keymap file applied.
+pub fn load() -> Vector<Shortcut> {
+    let saved: BTreeMap<String, String> = fs::read_to_string(KEYMAP_FILE)
+        .ok()
+        .and_then(|json| serde_json::from_str(&json).ok())
+        .unwrap_or_default();
+    defaults()
+        .into_iter()
+        .map(|mut shortcut| {
+            if let Some(binding) = saved.get(&shortcut.id) {
+                shortcut.binding = binding.clone();
+            }
+            shortcut
+        })
+        .collect()
+}
+
+/// Writes the bindings to the keymap file.
+pub fn save(shortcuts: &Vector<Shortcut>) -> io::Result<()> {
+    let bindings: BTreeMap<&str, &str> = shortcuts
+        .iter()
+        .map(|shortcut| (shortcut.id.as_str(), shortcut.binding.as_str()))
+        .collect();
+    let json = serde_json::to_string_pretty(&bindings)?;
+    fs::write(KEYMAP_FILE, json)
+}
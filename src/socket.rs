@@ -0,0 +1,141 @@
+use std::{collections::BTreeMap, fs, io, path::Path};
+
+use druid::{im::Vector, Data, Lens};
+use serde::{Deserialize, Serialize};
+
+use crate::data::Canvas;
+
+/// The file socket labels are kept in, inside the tileset folder.
+pub const SOCKETS_FILE: &str = "sockets.json";
+
+/// Which way a socket reads along its side: left to right for the top and bottom,
+/// top to bottom for the left and right.
+#[derive(Clone, Copy, Data, Debug, PartialEq, Serialize, Deserialize)]
+pub enum Orientation {
+    /// Reads the same both ways.
+    Symmetric,
+    Forward,
+    Reverse,
+}
+
+impl Orientation {
+    pub fn reversed(self) -> Self {
+        match self {
+            Orientation::Symmetric => Orientation::Symmetric,
+            Orientation::Forward => Orientation::Reverse,
+            Orientation::Reverse => Orientation::Forward,
+        }
+    }
+
+    pub fn next(self) -> Self {
+        match self {
+            Orientation::Symmetric => Orientation::Forward,
+            Orientation::Forward => Orientation::Reverse,
+            Orientation::Reverse => Orientation::Symmetric,
+        }
+    }
+}
+
+/// A named connection on one side of a module, such as "grass" or "road-center".
+/// An empty label leaves the side to pixel-exact matching.
+#[derive(Clone, Data, Debug, PartialEq, Lens, Serialize, Deserialize)]
+pub struct Socket {
+    pub label: String,
+    pub orientation: Orientation,
+}
+
+impl Socket {
+    pub fn empty() -> Self {
+        Self {
+            label: String::new(),
+            orientation: Orientation::Symmetric,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.label.trim().is_empty()
+    }
+
+    /// Whether two sockets facing each other across a shared side connect. Both
+    /// sides read in the same direction, so the orientations must be equal.
+    pub fn connects(&self, other: &Socket) -> bool {
+        self.label.trim() == other.label.trim() && self.orientation == other.orientation
+    }
+
+    fn reversed(&self) -> Socket {
+        Socket {
+            label: self.label.clone(),
+            orientation: self.orientation.reversed(),
+        }
+    }
+}
+
+/// The sockets of a canvas after a quarter turn clockwise, in up, right, down, left
+/// order. The sides that end up on the top and bottom now read the other way.
+pub fn rotate_sockets(sockets: &[Socket; 4]) -> [Socket; 4] {
+    [
+        sockets[3].reversed(),
+        sockets[0].clone(),
+        sockets[1].reversed(),
+        sockets[2].clone(),
+    ]
+}
+
+/// The sockets of a canvas after flipping it left to right.
+pub fn flip_sockets(sockets: &[Socket; 4]) -> [Socket; 4] {
+    [
+        sockets[0].reversed(),
+        sockets[3].clone(),
+        sockets[2].reversed(),
+        sockets[1].clone(),
+    ]
+}
+
+/// Edits one side's socket of a canvas, marking the canvas as changed.
+pub struct SocketLens(pub usize);
+
+impl Lens<Canvas, Socket> for SocketLens {
+    fn with<V, F: FnOnce(&Socket) -> V>(&self, data: &Canvas, f: F) -> V {
+        f(&data.sockets[self.0])
+    }
+
+    fn with_mut<V, F: FnOnce(&mut Socket) -> V>(&self, data: &mut Canvas, f: F) -> V {
+        let mut socket = data.sockets[self.0].clone();
+        let value = f(&mut socket);
+        if socket != data.sockets[self.0] {
+            data.sockets[self.0] = socket;
+            data.dirty = true;
+        }
+        value
+    }
+}
+
+/// Applies the sockets saved in the tileset folder `dir` to the modules by
+/// group-qualified name.
+pub fn load(dir: &Path, modules: &mut Vector<Canvas>) {
+    let saved: BTreeMap<String, [Socket; 4]> = fs::read_to_string(dir.join(SOCKETS_FILE))
+        .ok()
+        .and_then(|json| serde_json::from_str(&json).ok())
+        .unwrap_or_default();
+    for module in modules.iter_mut() {
+        if let Some(sockets) = saved.get(&module.key()) {
+            module.sockets = sockets.clone();
+        }
+    }
+}
+
+/// Writes the sockets of every module that has any to the tileset folder `dir`.
+pub fn save(dir: &Path, modules: &Vector<Canvas>) -> io::Result<()> {
+    let sockets: BTreeMap<String, &[Socket; 4]> = modules
+        .iter()
+        .filter(|module| module.sockets.iter().any(|socket| !socket.is_empty()))
+        .map(|module| (module.key(), &module.sockets))
+        .collect();
+    let path = dir.join(SOCKETS_FILE);
+    if sockets.is_empty() && !path.exists() {
+        return Ok(());
+    }
+    fs::create_dir_all(dir)?;
+    let json = serde_json::to_string_pretty(&sockets)?;
+    fs::write(path, json)
+}
@@ -0,0 +1,262 @@
+use druid::Data;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    data::Canvas,
+    socket::{flip_sockets, rotate_sockets},
+};
+
+/// One of the eight symmetries of a square tile: an optional horizontal flip
+/// followed by `rotation` quarter turns clockwise.
+#[derive(Clone, Copy, Data, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct Transform {
+    pub rotation: u8,
+    pub flip: bool,
+}
+
+impl Transform {
+    pub const IDENTITY: Transform = Transform {
+        rotation: 0,
+        flip: false,
+    };
+
+    pub fn all() -> Vec<Transform> {
+        let mut all = vec![];
+        for &flip in &[false, true] {
+            for rotation in 0..4 {
+                all.push(Transform { rotation, flip });
+            }
+        }
+        all
+    }
+
+    /// `self` followed by `next`, as a single transform.
+    pub fn then(self, next: Transform) -> Transform {
+        // A flip turns the rotations before it the other way round.
+        let rotation = if next.flip {
+            4 - self.rotation % 4
+        } else {
+            self.rotation % 4
+        };
+        Transform {
+            rotation: (next.rotation % 4 + rotation) % 4,
+            flip: self.flip != next.flip,
+        }
+    }
+
+    pub fn inverse(self) -> Transform {
+        if self.flip {
+            self
+        } else {
+            Transform {
+                rotation: (4 - self.rotation % 4) % 4,
+                flip: false,
+            }
+        }
+    }
+
+    /// Where side `side` (up, right, down, left) of a tile ends up.
+    pub fn map_side(&self, side: usize) -> usize {
+        let side = if self.flip { (4 - side) % 4 } else { side };
+        (side + self.rotation as usize) % 4
+    }
+
+    /// The size of a `width` by `height` canvas after the transform.
+    pub fn size(&self, width: usize, height: usize) -> (usize, usize) {
+        if self.rotation % 2 == 1 {
+            (height, width)
+        } else {
+            (width, height)
+        }
+    }
+
+    /// Where pixel (`x`, `y`) of a `width` by `height` canvas ends up.
+    pub fn map_point(&self, x: usize, y: usize, width: usize, height: usize) -> (usize, usize) {
+        let (mut x, mut y, mut width, mut height) = (x, y, width, height);
+        if self.flip {
+            x = width - 1 - x;
+        }
+        for _ in 0..self.rotation % 4 {
+            let rotated = (height - 1 - y, x);
+            x = rotated.0;
+            y = rotated.1;
+            std::mem::swap(&mut width, &mut height);
+        }
+        (x, y)
+    }
+
+    pub fn apply(&self, canvas: &Canvas) -> Canvas {
+        let mut canvas = canvas.clone();
+        canvas.transform(*self);
+        canvas
+    }
+}
+
+impl Canvas {
+    /// Transforms the pixels in place, carrying the socket labels along.
+    pub fn transform(&mut self, transform: Transform) {
+        let (width, height) = transform.size(self.width, self.height);
+        let inverse = transform.inverse();
+        self.remap(width, height, |x, y| inverse.map_point(x, y, width, height));
+        if transform.flip {
+            self.sockets = flip_sockets(&self.sockets);
+        }
+        for _ in 0..transform.rotation % 4 {
+            self.sockets = rotate_sockets(&self.sockets);
+        }
+    }
+
+    /// Every distinct transformed copy of this module, starting with the identity.
+    /// Transforms that produce the same pixels and sockets as an earlier one are
+    /// left out.
+    pub fn unique_variants(&self) -> Vec<(Transform, Canvas)> {
+        let mut variants: Vec<(Transform, Canvas)> = vec![];
+        for transform in Transform::all() {
+            let variant = transform.apply(self);
+            let seen = variants.iter().any(|(_, other)| {
+                other.width == variant.width
+                    && other.height == variant.height
+                    && other.storage == variant.storage
+                    && other.sockets == variant.sockets
+            });
+            if !seen {
+                variants.push((transform, variant));
+            }
+        }
+        variants
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use druid::Color;
+
+    use super::*;
+    use crate::{
+        data::CanvasPos,
+        socket::{Orientation, Socket},
+    };
+
+    /// A canvas whose pixels and sockets all differ, so any misplaced one shows.
+    fn canvas(width: usize, height: usize) -> Canvas {
+        let mut canvas = Canvas::with_size(0, width, height);
+        for row in 0..width {
+            for col in 0..height {
+                canvas[CanvasPos { row, col }] = Color::rgb8(row as u8 * 16, col as u8 * 16, 0);
+            }
+        }
+        let orientations = [
+            Orientation::Forward,
+            Orientation::Reverse,
+            Orientation::Symmetric,
+            Orientation::Forward,
+        ];
+        for (side, &orientation) in orientations.iter().enumerate() {
+            canvas.sockets[side] = Socket {
+                label: format!("side{}", side),
+                orientation,
+            };
+        }
+        canvas
+    }
+
+    fn canvases() -> Vec<Canvas> {
+        vec![canvas(4, 4), canvas(3, 5)]
+    }
+
+    #[test]
+    fn four_quarter_turns_are_the_identity() {
+        for original in canvases() {
+            let mut canvas = original.clone();
+            for _ in 0..4 {
+                canvas.rotate_90();
+            }
+            assert!(canvas == original);
+        }
+    }
+
+    #[test]
+    fn quarter_turns_swap_width_and_height() {
+        let mut canvas = Canvas::with_size(0, 3, 2);
+        let red = Color::rgb8(255, 0, 0);
+        canvas[CanvasPos { row: 2, col: 0 }] = red.clone();
+        canvas.rotate_90();
+        assert_eq!((canvas.width, canvas.height), (2, 3));
+        // The top-right pixel ends up bottom right.
+        assert!(canvas[CanvasPos { row: 1, col: 2 }] == red);
+    }
+
+    #[test]
+    fn flipping_twice_is_the_identity() {
+        for original in canvases() {
+            let mut canvas = original.clone();
+            canvas.flip_horizontal();
+            assert!(canvas != original);
+            canvas.flip_horizontal();
+            assert!(canvas == original);
+            canvas.flip_vertical();
+            canvas.flip_vertical();
+            assert!(canvas == original);
+            canvas.transpose();
+            canvas.transpose();
+            assert!(canvas == original);
+        }
+    }
+
+    #[test]
+    fn then_composes_like_applying_in_turn() {
+        for canvas in canvases() {
+            for a in Transform::all() {
+                for b in Transform::all() {
+                    assert!(
+                        a.then(b).apply(&canvas) == b.apply(&a.apply(&canvas)),
+                        "{:?} then {:?} on {}x{}",
+                        a,
+                        b,
+                        canvas.width,
+                        canvas.height
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn inverse_undoes_the_transform() {
+        for canvas in canvases() {
+            for t in Transform::all() {
+                assert_eq!(t.then(t.inverse()), Transform::IDENTITY);
+                assert_eq!(t.inverse().then(t), Transform::IDENTITY);
+                assert!(t.inverse().apply(&t.apply(&canvas)) == canvas, "{:?}", t);
+            }
+        }
+    }
+
+    #[test]
+    fn sides_move_with_their_sockets() {
+        for canvas in canvases() {
+            for t in Transform::all() {
+                let transformed = t.apply(&canvas);
+                for side in 0..4 {
+                    let label = &transformed.sockets[t.map_side(side)].label;
+                    assert_eq!(label, &canvas.sockets[side].label, "{:?}", t);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn the_eight_transforms_differ() {
+        for canvas in canvases() {
+            let results: Vec<Canvas> = Transform::all()
+                .into_iter()
+                .map(|t| t.apply(&canvas))
+                .collect();
+            for i in 0..results.len() {
+                for j in 0..i {
+                    assert!(results[i] != results[j]);
+                }
+            }
+        }
+    }
+}
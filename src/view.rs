@@ -1,22 +1,41 @@
 use druid::{
+    commands,
     im::Vector,
+    kurbo::{Circle, Line},
     lens,
+    piet::{ImageFormat, InterpolationMode},
     widget::Button,
+    widget::Checkbox,
+    widget::Either,
     widget::Label,
     widget::List,
     widget::Painter,
-    widget::{CrossAxisAlignment, Flex, MainAxisAlignment, SizedBox},
-    Color, LensExt, RenderContext, Widget, WidgetExt,
+    widget::Radio,
+    widget::Scroll,
+    widget::Stepper,
+    widget::TextBox,
+    widget::{CrossAxisAlignment, Flex, SizedBox},
+    Color, Data, EventCtx, FileDialogOptions, FileSpec, Lens, LensExt, Rect, RenderContext, Widget,
+    WidgetExt,
 };
 
 use crate::{
-    controllers::ContextMenuController,
+    adjacency::{AdjacencyRules, RuleMode},
+    border::{EdgeKind, MapBorder, MapEdge},
+    controllers::{ContextMenuController, MenuCheckController, ModuleContextMenuController},
     data::*,
-    widgets::{PaintCanvas, ViewCanvas},
+    diagnostics::{self, Diagnostics},
+    export::ExportState,
+    import::ImportState,
+    shortcuts::{self, Shortcut},
+    socket::{Orientation, Socket, SocketLens},
+    widgets::{
+        EntropyChart, MapCanvas, PaintCanvas, RuleMatrix, TilePreview, UsageHistogram, ViewCanvas,
+    },
 };
 
 fn single_color() -> impl Widget<(AppState, Color)> {
-    let my_painter = Painter::new(|ctx, (data, color): &(AppState, Color), env| {
+    let my_painter = Painter::new(|ctx, (data, color): &(AppState, Color), _env| {
         let bounds = ctx.size().to_rect();
         ctx.fill(bounds, color);
         if &data.selected_color == color {
@@ -37,17 +56,30 @@ fn single_color() -> impl Widget<(AppState, Color)> {
 }
 
 fn single_module() -> impl Widget<(AppState, Canvas)> {
-    let my_painter = Painter::new(|ctx, (data, module): &(AppState, Canvas), env| {
+    let my_painter = Painter::new(|ctx, (data, module): &(AppState, Canvas), _env| {
         let bounds = ctx.size().to_rect();
         if data.active_canvas_id == module.id {
             ctx.stroke(bounds.inset(-4.).floor(), &Color::BLACK, 2.);
             ctx.stroke(bounds.inset(-2.).floor(), &Color::WHITE, 2.);
         }
+        let index = data.find_index(module.id).unwrap_or(0);
+        if index > 0 && data.modules[index - 1].group != module.group {
+            let divider = Line::new((bounds.x0, bounds.y0 + 2.), (bounds.x0, bounds.y1 - 2.));
+            ctx.stroke(divider, &Color::grey(0.5), 2.);
+        }
+        if data.lint.iter().any(|finding| finding.applies_to(module)) {
+            ctx.stroke(bounds.inset(-1.), &Color::rgb8(220, 40, 40), 2.);
+        }
+        if module.dirty {
+            let marker = Circle::new((bounds.x1 - 8., bounds.y0 + 4.), 3.);
+            ctx.fill(marker, &Color::rgb8(255, 128, 0));
+            ctx.stroke(marker, &Color::BLACK, 1.);
+        }
     });
 
     let too_many_lenses = lens::Identity.map(
-        |(data, module): &(AppState, Canvas)| module.clone(),
-        |(data, module): &mut (AppState, Canvas), new_data| {
+        |(_, module): &(AppState, Canvas)| module.clone(),
+        |(_, module): &mut (AppState, Canvas), new_data| {
             *module = new_data;
         },
     );
@@ -60,6 +92,7 @@ fn single_module() -> impl Widget<(AppState, Canvas)> {
         .padding((0., 0., 2., 0.))
         .on_click(AppState::click_module)
         .background(my_painter)
+        .controller(ModuleContextMenuController)
 }
 
 pub fn modules() -> impl Widget<AppState> {
@@ -82,28 +115,757 @@ pub fn modules() -> impl Widget<AppState> {
     SizedBox::new(row).fix_height(32.).background(Color::WHITE)
 }
 
-fn single_map_module() -> impl Widget<Canvas> {
-    let single_module = SizedBox::new(ViewCanvas::new())
-        .fix_height(32.)
-        .fix_width(32.);
+pub fn map_window() -> impl Widget<AppState> {
+    let generate = Button::new("Generate").on_click(|ctx, _data: &mut AppState, _env| {
+        ctx.submit_command(GENERATE_MAP);
+    });
+    let regenerate =
+        Button::new("Regenerate Unpinned").on_click(|ctx, _data: &mut AppState, _env| {
+            ctx.submit_command(REGENERATE_UNPINNED);
+        });
+    let regenerate_selection =
+        Button::new("Regenerate Selection").on_click(|ctx, _data: &mut AppState, _env| {
+            ctx.submit_command(REGENERATE_SELECTION);
+        });
+    let undo = Button::new("Undo").on_click(|ctx, _data: &mut AppState, _env| {
+        ctx.submit_command(UNDO);
+    });
+    let infinite = Checkbox::new("Infinite").lens(AppState::infinite_map);
+    let periodic = Checkbox::new("Periodic").lens(AppState::periodic_map);
+    let tiled = Checkbox::new("Tiled 3x3 preview").lens(AppState::tiled_preview);
+    let grid = Checkbox::new("Grid").lens(AppState::show_grid);
+    let rulers = Checkbox::new("Rulers").lens(AppState::show_rulers);
+    let fit = Button::new("Fit to Window").on_click(|_ctx, data: &mut AppState, _env| {
+        data.map_view.fit = true;
+    });
+    let new_world = Button::new("New World").on_click(|ctx, _data: &mut AppState, _env| {
+        ctx.submit_command(NEW_WORLD);
+    });
+    let export = Button::new("Export Region...").on_click(|ctx, _data: &mut AppState, _env| {
+        ctx.submit_command(SHOW_REGION_WINDOW);
+    });
+    let rules = Button::new("Rules...").on_click(|ctx, _data: &mut AppState, _env| {
+        ctx.submit_command(SHOW_RULES_WINDOW);
+    });
+    let border = Button::new("Border...").on_click(|ctx, _data: &mut AppState, _env| {
+        ctx.submit_command(SHOW_BORDER_WINDOW);
+    });
+    let constraints = Button::new("Constraints...").on_click(|ctx, _data: &mut AppState, _env| {
+        ctx.submit_command(SHOW_CONSTRAINTS_WINDOW);
+    });
+    let status = Label::new(|data: &AppState, _env: &_| data.status.clone());
 
-    single_module
+    Flex::column()
+        .with_flex_child(MapCanvas::new(), 1.)
+        .with_spacer(4.)
+        .with_child(
+            Flex::row()
+                .with_child(generate)
+                .with_spacer(4.)
+                .with_child(regenerate)
+                .with_spacer(4.)
+                .with_child(regenerate_selection)
+                .with_spacer(4.)
+                .with_child(undo),
+        )
+        .with_spacer(4.)
+        .with_child(
+            Flex::row()
+                .with_child(periodic)
+                .with_spacer(4.)
+                .with_child(tiled)
+                .with_spacer(4.)
+                .with_child(infinite)
+                .with_spacer(4.)
+                .with_child(new_world)
+                .with_spacer(4.)
+                .with_child(export),
+        )
+        .with_spacer(4.)
+        .with_child(
+            Flex::row()
+                .with_child(grid)
+                .with_spacer(4.)
+                .with_child(rulers)
+                .with_spacer(4.)
+                .with_child(fit)
+                .with_spacer(4.)
+                .with_child(rules)
+                .with_spacer(4.)
+                .with_child(border)
+                .with_spacer(4.)
+                .with_child(constraints),
+        )
+        .with_spacer(4.)
+        .with_child(status)
+        .padding(4.)
 }
 
-fn map_row() -> impl Widget<Vector<Canvas>> {
-    List::new(single_map_module).horizontal()
+pub fn region_window() -> impl Widget<AppState> {
+    let export = Button::new("Export PNG...").on_click(|ctx, _data: &mut Region, _env| {
+        let options = FileDialogOptions::new()
+            .allowed_types(vec![FileSpec::PNG])
+            .default_name("region.png")
+            .accept_command(EXPORT_REGION);
+        ctx.submit_command(commands::SHOW_SAVE_PANEL.with(options));
+    });
+    let close = Button::new("Close").on_click(|ctx, _data: &mut Region, _env| {
+        ctx.submit_command(commands::CLOSE_WINDOW.to(ctx.window_id()));
+    });
+
+    Flex::column()
+        .cross_axis_alignment(CrossAxisAlignment::Start)
+        .with_child(number_field("X", -1e9, 1e9, Region::x))
+        .with_child(number_field("Y", -1e9, 1e9, Region::y))
+        .with_child(number_field("Width", 1., 1024., Region::width))
+        .with_child(number_field("Height", 1., 1024., Region::height))
+        .with_spacer(8.)
+        .with_child(
+            Flex::row()
+                .with_child(export)
+                .with_spacer(4.)
+                .with_child(close),
+        )
+        .padding(8.)
+        .lens(AppState::region)
 }
 
-fn map_grid() -> impl Widget<Vector<Vector<Canvas>>> {
-    List::new(map_row)
+pub fn rename_window() -> impl Widget<AppState> {
+    let name = TextBox::new().lens(AppState::rename_text).expand_width();
+
+    let rename = Button::new("Rename").on_click(|ctx, _data: &mut AppState, _env| {
+        ctx.submit_command(APPLY_RENAME);
+        ctx.submit_command(commands::CLOSE_WINDOW.to(ctx.window_id()));
+    });
+
+    Flex::row()
+        .with_flex_child(name, 1.)
+        .with_spacer(4.)
+        .with_child(rename)
+        .padding(8.)
 }
 
-pub fn map_window() -> impl Widget<AppState> {
-    map_grid().lens(AppState::map)
+fn shortcut_row() -> impl Widget<Shortcut> {
+    let title = Label::new(|shortcut: &Shortcut, _env: &_| shortcut.title.clone()).fix_width(160.);
+    let binding = TextBox::new().lens(Shortcut::binding).fix_width(140.);
+    let problem = Label::new(|shortcut: &Shortcut, _env: &_| {
+        if shortcut.binding.trim().is_empty() || shortcut.parse().is_some() {
+            String::new()
+        } else {
+            "not recognised".to_string()
+        }
+    });
+
+    Flex::row()
+        .with_child(title)
+        .with_child(binding)
+        .with_spacer(4.)
+        .with_child(problem)
+        .padding((0., 2.))
+}
+
+pub fn shortcuts_window() -> impl Widget<AppState> {
+    let list = Scroll::new(List::new(shortcut_row))
+        .vertical()
+        .lens(AppState::shortcuts);
+
+    let reset = Button::new("Reset to Defaults").on_click(|_ctx, data: &mut AppState, _env| {
+        data.shortcuts = shortcuts::defaults();
+    });
+    let apply = Button::new("Apply").on_click(|ctx, _data: &mut AppState, _env| {
+        ctx.submit_command(APPLY_SHORTCUTS);
+        ctx.submit_command(commands::CLOSE_WINDOW.to(ctx.window_id()));
+    });
+
+    Flex::column()
+        .with_child(Label::new(
+            "Type a binding such as Cmd+Shift+S, or leave it empty for none.",
+        ))
+        .with_spacer(4.)
+        .with_flex_child(list, 1.)
+        .with_spacer(8.)
+        .with_child(
+            Flex::row()
+                .with_child(reset)
+                .with_flex_spacer(1.)
+                .with_child(apply),
+        )
+        .padding(8.)
+}
+
+pub fn rules_window() -> impl Widget<AppState> {
+    let mode = Flex::row()
+        .with_child(Label::new("Use:"))
+        .with_child(Radio::new("Matching edges", RuleMode::Derived))
+        .with_child(Radio::new("These rules", RuleMode::Custom))
+        .with_child(Radio::new("Both", RuleMode::Restrict))
+        .lens(AppState::adjacency.then(AdjacencyRules::mode));
+    let direction = Flex::row()
+        .with_child(Label::new("Neighbour:"))
+        .with_child(Radio::new("Above", 0))
+        .with_child(Radio::new("Right", 1))
+        .with_child(Radio::new("Below", 2))
+        .with_child(Radio::new("Left", 3))
+        .lens(AppState::rule_direction);
+
+    let copy = Button::new("Copy Matching Edges").on_click(|_ctx, data: &mut AppState, _env| {
+        data.adjacency.copy_derived(&data.modules);
+    });
+    let symmetric = Button::new("Make Symmetric").on_click(|_ctx, data: &mut AppState, _env| {
+        data.adjacency.make_symmetric();
+    });
+    let clear = Button::new("Clear").on_click(|_ctx, data: &mut AppState, _env| {
+        data.adjacency.clear();
+    });
+    let save = Button::new("Save").on_click(|_ctx, data: &mut AppState, _env| {
+        data.save_adjacency();
+    });
+
+    Flex::column()
+        .cross_axis_alignment(CrossAxisAlignment::Start)
+        .with_child(mode)
+        .with_spacer(4.)
+        .with_child(direction)
+        .with_spacer(4.)
+        .with_child(Label::new(
+            "Rows are modules, columns the neighbours they allow. Dots mark matching edges.",
+        ))
+        .with_spacer(4.)
+        .with_flex_child(RuleMatrix::new(), 1.)
+        .with_spacer(8.)
+        .with_child(
+            Flex::row()
+                .with_child(copy)
+                .with_spacer(4.)
+                .with_child(symmetric)
+                .with_spacer(4.)
+                .with_child(clear)
+                .with_spacer(4.)
+                .with_child(save),
+        )
+        .padding(8.)
+}
+
+fn edge_settings(title: &str) -> impl Widget<MapEdge> {
+    let kind = Flex::row()
+        .with_child(Radio::new("Any", EdgeKind::Any))
+        .with_child(Radio::new("Only", EdgeKind::Modules))
+        .with_child(Radio::new("Socket", EdgeKind::Socket))
+        .lens(MapEdge::kind);
+    let modules = TextBox::new()
+        .with_placeholder("water,sand")
+        .fix_width(120.)
+        .lens(MapEdge::modules);
+    let socket = Flex::row()
+        .with_child(
+            TextBox::new()
+                .with_placeholder("socket")
+                .lens(Socket::label)
+                .fix_width(90.),
+        )
+        .with_child(orientation_button())
+        .lens(MapEdge::socket);
+    let setting = Either::new(
+        |edge: &MapEdge, _env| edge.kind == EdgeKind::Socket,
+        socket,
+        modules,
+    );
+
+    Flex::row()
+        .with_child(Label::new(title).fix_width(56.))
+        .with_child(kind)
+        .with_spacer(4.)
+        .with_child(setting)
+}
+
+pub fn border_window() -> impl Widget<AppState> {
+    Flex::column()
+        .cross_axis_alignment(CrossAxisAlignment::Start)
+        .with_child(Label::new(
+            "Tiles allowed along each edge of the map. Ignored when the map wraps.",
+        ))
+        .with_child(Label::new(
+            "A socket edge only takes tiles with a matching socket label on that side.",
+        ))
+        .with_spacer(8.)
+        .with_child(edge_settings("Top").lens(AppState::map_border.then(MapBorder::top)))
+        .with_spacer(4.)
+        .with_child(edge_settings("Right").lens(AppState::map_border.then(MapBorder::right)))
+        .with_spacer(4.)
+        .with_child(edge_settings("Bottom").lens(AppState::map_border.then(MapBorder::bottom)))
+        .with_spacer(4.)
+        .with_child(edge_settings("Left").lens(AppState::map_border.then(MapBorder::left)))
+        .padding(8.)
+}
+
+pub fn diagnostics_window() -> impl Widget<AppState> {
+    let runs = Label::new(|data: &Diagnostics, _env: &_| {
+        if data.runs.is_empty() {
+            return "Generate a map to collect statistics.".to_string();
+        }
+        data.runs
+            .iter()
+            .rev()
+            .map(|run| {
+                format!(
+                    "Seed {}: {} attempt(s), {} contradiction(s) ({:.0}%){}",
+                    run.seed,
+                    run.attempts,
+                    run.contradictions,
+                    100. * run.contradiction_rate(),
+                    if run.succeeded { "" } else { ", failed" }
+                )
+            })
+            .collect::<Vec<String>>()
+            .join("\n")
+    });
+    let dead_ends = Label::new(|data: &Diagnostics, _env: &_| {
+        if data.runs.is_empty() {
+            return "Generate a map to check the modules.".to_string();
+        }
+        if data.dead_ends.is_empty() {
+            return "Every module has a possible neighbour on each side.".to_string();
+        }
+        data.dead_ends
+            .iter()
+            .map(|(name, dirs)| format!("{}: nothing fits {}", name, diagnostics::directions(dirs)))
+            .collect::<Vec<String>>()
+            .join("\n")
+    });
+    let export = Button::new("Export CSV...").on_click(|ctx, _data: &mut AppState, _env| {
+        let options = FileDialogOptions::new()
+            .allowed_types(vec![FileSpec::new("CSV", &["csv"])])
+            .default_name("diagnostics.csv")
+            .accept_command(EXPORT_DIAGNOSTICS);
+        ctx.submit_command(commands::SHOW_SAVE_PANEL.with(options));
+    });
+    let clear = Button::new("Clear").on_click(|_ctx, data: &mut AppState, _env| {
+        data.diagnostics = Diagnostics::new();
+    });
+
+    let report = Flex::column()
+        .cross_axis_alignment(CrossAxisAlignment::Start)
+        .with_child(Label::new(format!(
+            "Module usage over the last {} generations",
+            diagnostics::HISTORY
+        )))
+        .with_spacer(4.)
+        .with_child(UsageHistogram.lens(AppState::diagnostics))
+        .with_spacer(8.)
+        .with_child(Label::new("Mean cell entropy from start to finish"))
+        .with_spacer(4.)
+        .with_child(EntropyChart.lens(AppState::diagnostics))
+        .with_spacer(8.)
+        .with_child(Label::new("Contradictions per seed, newest first"))
+        .with_spacer(4.)
+        .with_child(runs.lens(AppState::diagnostics))
+        .with_spacer(8.)
+        .with_child(Label::new(
+            "Modules that could never be placed in the last generation",
+        ))
+        .with_spacer(4.)
+        .with_child(dead_ends.lens(AppState::diagnostics))
+        .padding(8.);
+
+    Flex::column()
+        .with_flex_child(Scroll::new(report).vertical(), 1.)
+        .with_child(
+            Flex::row()
+                .with_child(export)
+                .with_spacer(4.)
+                .with_child(clear)
+                .padding(8.),
+        )
+}
+
+pub fn biomes_window() -> impl Widget<AppState> {
+    let help = Label::new(
+        "Generate the map with this tileset's modules as biomes, then expand each\n\
+         cell into a block of detail tiles. One per line: block <size>, then\n\
+         <module> <detail tileset folder> with folders relative to this tileset.",
+    );
+    let text = TextBox::multiline()
+        .with_placeholder("block 4")
+        .expand()
+        .lens(AppState::biomes);
+    let save = Button::new("Save").on_click(|_ctx, data: &mut AppState, _env| {
+        data.save_biomes();
+    });
+    let expand = Button::new("Expand to PNG...").on_click(|ctx, _data: &mut AppState, _env| {
+        let options = FileDialogOptions::new()
+            .allowed_types(vec![FileSpec::PNG])
+            .default_name("detail.png")
+            .accept_command(EXPAND_BIOMES);
+        ctx.submit_command(commands::SHOW_SAVE_PANEL.with(options));
+    });
+    let status = Label::new(|data: &AppState, _env: &_| data.status.clone());
+
+    Flex::column()
+        .cross_axis_alignment(CrossAxisAlignment::Start)
+        .with_child(help)
+        .with_spacer(4.)
+        .with_flex_child(text, 1.)
+        .with_spacer(4.)
+        .with_child(
+            Flex::row()
+                .with_child(save)
+                .with_spacer(4.)
+                .with_child(expand),
+        )
+        .with_spacer(4.)
+        .with_child(status)
+        .padding(8.)
+}
+
+pub fn constraints_window() -> impl Widget<AppState> {
+    let help = Label::new(
+        "One per line: count <modules> <min> <max>, place <modules> <row> <col>,\n\
+         border <modules> [top right bottom left], connected <modules>.\n\
+         Modules are comma separated names, written group/name where a name is\n\
+         used in more than one group; counts may end in % of the map.",
+    );
+    let text = TextBox::multiline()
+        .with_placeholder("count castle 1 1")
+        .expand()
+        .lens(AppState::constraints);
+    let error = Label::new(|data: &AppState, _env: &_| data.constraint_error())
+        .with_text_color(Color::rgb8(220, 80, 80));
+    let save = Button::new("Save").on_click(|_ctx, data: &mut AppState, _env| {
+        data.save_constraints();
+    });
+
+    Flex::column()
+        .cross_axis_alignment(CrossAxisAlignment::Start)
+        .with_child(help)
+        .with_spacer(4.)
+        .with_flex_child(text, 1.)
+        .with_spacer(4.)
+        .with_child(error)
+        .with_spacer(4.)
+        .with_child(save)
+        .padding(8.)
+}
+
+pub fn unsaved_changes_window() -> impl Widget<AppState> {
+    let message = Label::new(|data: &AppState, _env: &_| {
+        let count = data.modules.iter().filter(|module| module.dirty).count();
+        if data.adjacency.dirty
+            || data.constraints != data.saved_constraints
+            || data.biomes != data.saved_biomes
+        {
+            format!(
+                "{} module(s) and the generation rules have unsaved changes.",
+                count
+            )
+        } else {
+            format!("{} module(s) have unsaved changes.", count)
+        }
+    });
+
+    // The prompt guards both quitting and opening another tileset.
+    let verb = |data: &AppState| {
+        if data.pending_tileset.is_some() {
+            "Open"
+        } else {
+            "Quit"
+        }
+    };
+    let answer = |ctx: &mut EventCtx, data: &mut AppState, save: bool| {
+        if data.pending_tileset.is_some() {
+            ctx.submit_command(OPEN_PENDING_TILESET.with(save));
+            ctx.submit_command(commands::CLOSE_WINDOW.to(ctx.window_id()));
+        } else {
+            ctx.submit_command(QUIT.with(save));
+        }
+    };
+    let save =
+        Button::dynamic(move |data: &AppState, _env: &_| format!("Save All and {}", verb(data)))
+            .on_click(move |ctx, data: &mut AppState, _env| answer(ctx, data, true));
+    let discard =
+        Button::dynamic(move |data: &AppState, _env: &_| format!("{} Without Saving", verb(data)))
+            .on_click(move |ctx, data: &mut AppState, _env| answer(ctx, data, false));
+    let cancel = Button::new("Cancel").on_click(|ctx, data: &mut AppState, _env| {
+        data.pending_tileset = None;
+        ctx.submit_command(commands::CLOSE_WINDOW.to(ctx.window_id()));
+    });
+
+    Flex::column()
+        .with_child(message)
+        .with_spacer(8.)
+        .with_child(
+            Flex::row()
+                .with_child(save)
+                .with_spacer(4.)
+                .with_child(discard)
+                .with_spacer(4.)
+                .with_child(cancel),
+        )
+        .padding(8.)
+}
+
+fn number_field<T: Data>(
+    label: &str,
+    min: f64,
+    max: f64,
+    lens: impl Lens<T, f64> + 'static,
+) -> impl Widget<T> {
+    let value = Label::new(|value: &f64, _env: &_| format!("{}", value));
+    let stepper = Stepper::new().with_range(min, max).with_step(1.);
+
+    Flex::row()
+        .with_child(Label::new(label).fix_width(90.))
+        .with_child(value.fix_width(40.))
+        .with_child(stepper)
+        .lens(lens)
+}
+
+fn import_preview() -> impl Widget<ImportState> {
+    let preview = Painter::new(|ctx, data: &ImportState, _env| {
+        let atlas = match &data.atlas {
+            Some(atlas) => atlas,
+            None => return,
+        };
+        let bounds = ctx.size().to_rect();
+        let (width, height) = (atlas.width() as f64, atlas.height() as f64);
+        let scale = (bounds.width() / width).min(bounds.height() / height);
+
+        let image = ctx
+            .make_image(
+                atlas.width() as usize,
+                atlas.height() as usize,
+                atlas.as_raw(),
+                ImageFormat::RgbaSeparate,
+            )
+            .unwrap();
+        let dest = Rect::new(0., 0., width * scale, height * scale);
+        ctx.draw_image(&image, dest, InterpolationMode::NearestNeighbor);
+
+        for tile in data.slice() {
+            let rect = Rect::from_origin_size(
+                (tile.x as f64 * scale, tile.y as f64 * scale),
+                (
+                    tile.image.width() as f64 * scale,
+                    tile.image.height() as f64 * scale,
+                ),
+            );
+            if tile.skipped {
+                let cross = Line::new((rect.x0, rect.y0), (rect.x1, rect.y1));
+                ctx.stroke(cross, &Color::rgb8(200, 0, 0), 1.);
+                ctx.stroke(rect, &Color::rgb8(200, 0, 0), 1.);
+            } else {
+                ctx.stroke(rect, &Color::rgb8(0, 200, 0), 1.);
+            }
+        }
+    });
+
+    SizedBox::empty().expand().background(preview)
+}
+
+pub fn import_window() -> impl Widget<AppState> {
+    let source = Label::new(|data: &ImportState, _env: &_| match &data.atlas {
+        Some(atlas) => format!("{} ({}x{})", data.stem(), atlas.width(), atlas.height()),
+        None => format!("Could not read {}", data.stem()),
+    });
+
+    let count = Label::new(|data: &ImportState, _env: &_| {
+        let tiles = data.slice();
+        let kept = tiles.iter().filter(|tile| !tile.skipped).count();
+        format!("{} of {} tiles will be imported", kept, tiles.len())
+    });
+
+    let import = Button::new("Import").on_click(|ctx, _data: &mut ImportState, _env| {
+        ctx.submit_command(APPLY_IMPORT);
+        ctx.submit_command(commands::CLOSE_WINDOW.to(ctx.window_id()));
+    });
+    let cancel = Button::new("Cancel").on_click(|ctx, _data: &mut ImportState, _env| {
+        ctx.submit_command(commands::CLOSE_WINDOW.to(ctx.window_id()));
+    });
+
+    Flex::column()
+        .cross_axis_alignment(CrossAxisAlignment::Start)
+        .with_child(source)
+        .with_spacer(4.)
+        .with_child(number_field(
+            "Tile width",
+            1.,
+            256.,
+            ImportState::tile_width,
+        ))
+        .with_child(number_field(
+            "Tile height",
+            1.,
+            256.,
+            ImportState::tile_height,
+        ))
+        .with_child(number_field("Margin", 0., 256., ImportState::margin))
+        .with_child(number_field("Spacing", 0., 256., ImportState::spacing))
+        .with_spacer(4.)
+        .with_child(Checkbox::new("Skip empty tiles").lens(ImportState::skip_empty))
+        .with_child(Checkbox::new("Skip duplicate tiles").lens(ImportState::skip_duplicates))
+        .with_spacer(4.)
+        .with_flex_child(import_preview(), 1.)
+        .with_spacer(4.)
+        .with_child(count)
+        .with_spacer(4.)
+        .with_child(
+            Flex::row()
+                .with_child(import)
+                .with_spacer(4.)
+                .with_child(cancel),
+        )
+        .padding(8.)
+        .lens(AppState::import)
+}
+
+pub fn export_window() -> impl Widget<AppState> {
+    let variants =
+        Checkbox::new("Include rotated and flipped variants").lens(ExportState::include_variants);
+
+    let export = Button::new("Export...").on_click(|ctx, _data: &mut ExportState, _env| {
+        let options = FileDialogOptions::new()
+            .allowed_types(vec![FileSpec::PNG])
+            .default_name("tileset.png")
+            .accept_command(EXPORT_TILESET);
+        ctx.submit_command(commands::SHOW_SAVE_PANEL.with(options));
+    });
+    let close = Button::new("Close").on_click(|ctx, _data: &mut ExportState, _env| {
+        ctx.submit_command(commands::CLOSE_WINDOW.to(ctx.window_id()));
+    });
+
+    Flex::column()
+        .cross_axis_alignment(CrossAxisAlignment::Start)
+        .with_child(variants)
+        .with_spacer(4.)
+        .with_child(number_field("Padding", 0., 256., ExportState::padding))
+        .with_child(number_field("Extrusion", 0., 256., ExportState::extrude))
+        .with_spacer(8.)
+        .with_child(
+            Flex::row()
+                .with_child(export)
+                .with_spacer(4.)
+                .with_child(close),
+        )
+        .padding(8.)
+        .lens(AppState::export)
+}
+
+fn drawing_tools() -> impl Widget<AppState> {
+    let mirror = Flex::row()
+        .with_child(Label::new("Mirror:"))
+        .with_child(Radio::new("Off", Mirror::Off))
+        .with_child(Radio::new("H", Mirror::Horizontal))
+        .with_child(Radio::new("V", Mirror::Vertical))
+        .with_child(Radio::new("Both", Mirror::Both))
+        .with_child(Radio::new("Rotate", Mirror::Rotational))
+        .with_child(Radio::new("Diagonal", Mirror::Diagonal))
+        .lens(AppState::editor.then(EditorView::mirror));
+    let wrap = Checkbox::new("Wrap").lens(AppState::editor.then(EditorView::wrap));
+    let sockets = Checkbox::new("Sockets").lens(AppState::editor.then(EditorView::show_sockets));
+    let tool = Flex::row()
+        .with_child(Radio::new("Pencil", Tool::Pencil))
+        .with_child(Radio::new("Select", Tool::Select))
+        .lens(AppState::editor.then(EditorView::tool));
+
+    Flex::row()
+        .with_child(tool)
+        .with_spacer(8.)
+        .with_child(mirror)
+        .with_flex_spacer(1.)
+        .with_child(wrap)
+        .with_spacer(4.)
+        .with_child(sockets)
+}
+
+fn status_bar() -> impl Widget<AppState> {
+    let pixel_grid = Checkbox::new("Grid")
+        .controller(MenuCheckController)
+        .lens(AppState::editor.then(EditorView::pixel_grid));
+    let major_grid =
+        Checkbox::new("Major grid every").lens(AppState::editor.then(EditorView::major_grid));
+    let major_every = Label::new(|value: &f64, _env: &_| format!("{}", value))
+        .fix_width(24.)
+        .lens(AppState::editor.then(EditorView::major_every));
+    let stepper = Stepper::new()
+        .with_range(2., 64.)
+        .with_step(1.)
+        .lens(AppState::editor.then(EditorView::major_every));
+    let preview = Checkbox::new("Preview")
+        .controller(MenuCheckController)
+        .lens(AppState::tile_preview);
+    let neighbours = Checkbox::new("Neighbours").lens(AppState::preview_neighbours);
+    let hover = Label::new(|data: &AppState, _env: &_| data.hover_text());
+
+    Flex::row()
+        .with_child(pixel_grid)
+        .with_spacer(8.)
+        .with_child(major_grid)
+        .with_spacer(4.)
+        .with_child(major_every)
+        .with_child(stepper)
+        .with_spacer(8.)
+        .with_child(preview)
+        .with_spacer(4.)
+        .with_child(neighbours)
+        .with_flex_spacer(1.)
+        .with_child(hover)
+}
+
+fn socket_field(side: usize) -> impl Widget<AppState> {
+    let label = TextBox::new()
+        .with_placeholder("socket")
+        .lens(Socket::label)
+        .fix_width(90.);
+
+    Flex::row()
+        .with_child(label)
+        .with_child(orientation_button())
+        .lens(CanvasLens.then(SocketLens(side)))
+}
+
+/// Shows a socket's orientation and cycles through them when clicked.
+fn orientation_button() -> impl Widget<Socket> {
+    Button::dynamic(|socket: &Socket, _env: &_| {
+        match socket.orientation {
+            Orientation::Symmetric => "=",
+            Orientation::Forward => "→",
+            Orientation::Reverse => "←",
+        }
+        .to_string()
+    })
+    .on_click(|_ctx, socket: &mut Socket, _env| {
+        socket.orientation = socket.orientation.next();
+    })
+}
+
+fn paint_canvas() -> impl Widget<AppState> {
+    PaintCanvas::new()
+        .controller(ContextMenuController)
+        .lens(EditorLens)
+}
+
+/// The paint canvas with a socket label field on each side.
+fn socket_editor() -> impl Widget<AppState> {
+    let middle = Flex::row()
+        .with_child(socket_field(3))
+        .with_spacer(2.)
+        .with_flex_child(paint_canvas(), 1.)
+        .with_spacer(2.)
+        .with_child(socket_field(1));
+
+    Flex::column()
+        .with_child(socket_field(0))
+        .with_spacer(2.)
+        .with_flex_child(middle, 1.)
+        .with_spacer(2.)
+        .with_child(socket_field(2))
 }
 
 pub fn build_ui() -> impl Widget<AppState> {
-    let canvas = PaintCanvas::new();
+    let canvas = Either::new(
+        |data: &AppState, _env| data.editor.show_sockets,
+        socket_editor(),
+        paint_canvas(),
+    );
 
     let palette_lens = lens::Identity.map(
         |data: &AppState| (data.clone(), data.palette.clone()),
@@ -114,15 +876,24 @@ pub fn build_ui() -> impl Widget<AppState> {
 
     let row = Flex::row()
         .cross_axis_alignment(CrossAxisAlignment::Start)
-        .with_flex_child(
-            canvas.controller(ContextMenuController).lens(CanvasLens),
-            1.,
-        )
+        .with_flex_child(canvas, 1.)
+        .with_child(Either::new(
+            |data: &AppState, _env| data.tile_preview,
+            SizedBox::new(TilePreview::new())
+                .fix_width(144.)
+                .fix_height(144.)
+                .padding((2., 0., 0., 0.)),
+            SizedBox::empty(),
+        ))
         .with_spacer(2.)
         .with_child(List::new(single_color).lens(palette_lens));
 
     Flex::column()
-        .with_child(row)
+        .with_flex_child(row, 1.)
+        .with_spacer(2.)
+        .with_child(drawing_tools())
+        .with_spacer(2.)
+        .with_child(status_bar())
         .with_spacer(2.)
         .with_child(modules())
         .background(Color::WHITE)
@@ -0,0 +1,364 @@
+use std::collections::HashMap;
+
+use druid::im::Vector;
+use rand::Rng;
+
+use crate::{
+    adjacency::{AdjacencyRules, RuleMode},
+    data::{Canvas, CanvasPos},
+    transform::Transform,
+};
+
+pub fn opposite(dir: usize) -> usize {
+    (dir + 2) % 4
+}
+
+/// Row and column offsets for each direction, in up, right, down, left order.
+pub const OFFSETS: [(isize, isize); 4] = [(-1, 0), (0, 1), (1, 0), (0, -1)];
+
+/// A placeable tile: one unique variant of a module.
+pub struct Tile {
+    pub module_id: usize,
+    pub transform: Transform,
+    pub canvas: Canvas,
+}
+
+/// The tiles generation can place and which of them may sit next to each other.
+pub struct Rules {
+    pub tiles: Vec<Tile>,
+    lookup: HashMap<(usize, Transform), usize>,
+    /// `compatible[dir][a][b]` is true when `b` may be placed in direction `dir` of `a`.
+    compatible: [Vec<Vec<bool>>; 4],
+}
+
+impl Rules {
+    /// Derives adjacency over every unique variant of every module. Sides where
+    /// both tiles have a socket label connect when the sockets do; other sides
+    /// connect when their pixels match exactly.
+    pub fn from_modules(modules: &Vector<Canvas>) -> Self {
+        let mut tiles = vec![];
+        let mut lookup = HashMap::new();
+        for module in modules.iter() {
+            let first = tiles.len();
+            for (transform, canvas) in module.unique_variants() {
+                tiles.push(Tile {
+                    module_id: module.id,
+                    transform,
+                    canvas,
+                });
+            }
+            for transform in Transform::all() {
+                let variant = transform.apply(module);
+                let index = (first..tiles.len())
+                    .find(|&i| {
+                        tiles[i].canvas.storage == variant.storage
+                            && tiles[i].canvas.sockets == variant.sockets
+                    })
+                    .unwrap_or(first);
+                lookup.insert((module.id, transform), index);
+            }
+        }
+
+        let edges: Vec<[Vec<u32>; 4]> = tiles.iter().map(|tile| edges(&tile.canvas)).collect();
+        let mut compatible = [vec![], vec![], vec![], vec![]];
+        for dir in 0..4 {
+            compatible[dir] = (0..tiles.len())
+                .map(|a| {
+                    (0..tiles.len())
+                        .map(|b| {
+                            let from = &tiles[a].canvas.sockets[dir];
+                            let to = &tiles[b].canvas.sockets[opposite(dir)];
+                            if from.is_empty() || to.is_empty() {
+                                edges[a][dir] == edges[b][opposite(dir)]
+                            } else {
+                                from.connects(to)
+                            }
+                        })
+                        .collect()
+                })
+                .collect();
+        }
+
+        Self {
+            tiles,
+            lookup,
+            compatible,
+        }
+    }
+
+    /// The derived rules combined with `adjacency` according to its mode.
+    ///
+    /// Hand-written rules name the sides of untransformed modules, so each pair
+    /// of variants is checked on the original sides that end up facing each other.
+    pub fn build(modules: &Vector<Canvas>, adjacency: &AdjacencyRules) -> Self {
+        let mut rules = Self::from_modules(modules);
+        if adjacency.mode == RuleMode::Derived {
+            return rules;
+        }
+        let keys: HashMap<usize, String> = modules
+            .iter()
+            .map(|module| (module.id, module.key()))
+            .collect();
+        let key = |tile: &Tile| keys[&tile.module_id].as_str();
+        for dir in 0..4 {
+            for (a, from) in rules.tiles.iter().enumerate() {
+                for (b, to) in rules.tiles.iter().enumerate() {
+                    let side = from.transform.inverse().map_side(dir);
+                    let other = to.transform.inverse().map_side(opposite(dir));
+                    let allowed =
+                        other == opposite(side) && adjacency.allows(key(from), side, key(to));
+                    let compatible = &mut rules.compatible[dir][a][b];
+                    *compatible = match adjacency.mode {
+                        RuleMode::Custom => allowed,
+                        _ => *compatible && allowed,
+                    };
+                }
+            }
+        }
+        rules
+    }
+
+    /// Rules for several tilesets solved as one wave. Tiles of one set follow
+    /// that set's own `rules.json`; tiles of different sets meet by matching edges.
+    pub fn combine(sets: &[(Vector<Canvas>, AdjacencyRules)]) -> Self {
+        let modules: Vector<Canvas> = sets
+            .iter()
+            .flat_map(|(modules, _)| modules.iter().cloned())
+            .collect();
+        let mut rules = Self::from_modules(&modules);
+        for (modules, adjacency) in sets {
+            let own = Self::build(modules, adjacency);
+            let index: Vec<usize> = own
+                .tiles
+                .iter()
+                .map(|tile| rules.lookup[&(tile.module_id, tile.transform)])
+                .collect();
+            for dir in 0..4 {
+                for a in 0..own.len() {
+                    for b in 0..own.len() {
+                        rules.compatible[dir][index[a]][index[b]] = own.compatible[dir][a][b];
+                    }
+                }
+            }
+        }
+        rules
+    }
+
+    pub fn len(&self) -> usize {
+        self.tiles.len()
+    }
+
+    /// The tile index that places `module_id` with `transform`, if the module exists.
+    pub fn tile_index(&self, module_id: usize, transform: Transform) -> Option<usize> {
+        self.lookup.get(&(module_id, transform)).copied()
+    }
+
+    /// Which tiles may be placed in direction `dir` of `tile`.
+    pub fn neighbours(&self, dir: usize, tile: usize) -> &[bool] {
+        &self.compatible[dir][tile]
+    }
+
+    pub fn compatible(&self, dir: usize, a: usize, b: usize) -> bool {
+        self.compatible[dir][a][b]
+    }
+}
+
+/// The pixels along each side of a canvas, each read left to right or top to
+/// bottom, in up, right, down, left order.
+fn edges(canvas: &Canvas) -> [Vec<u32>; 4] {
+    let pixel = |x: usize, y: usize| canvas[CanvasPos { row: x, col: y }].as_rgba_u32();
+    let (w, h) = (canvas.width, canvas.height);
+    [
+        (0..w).map(|x| pixel(x, 0)).collect(),
+        (0..h).map(|y| pixel(w - 1, y)).collect(),
+        (0..w).map(|x| pixel(x, h - 1)).collect(),
+        (0..h).map(|y| pixel(0, y)).collect(),
+    ]
+}
+
+/// Brings `i` into `0..len`, wrapping around if `wraps` and giving up otherwise.
+pub fn wrap(i: isize, len: usize, wraps: bool) -> Option<usize> {
+    if i >= 0 && i < len as isize {
+        Some(i as usize)
+    } else if wraps && len > 0 {
+        Some(i.rem_euclid(len as isize) as usize)
+    } else {
+        None
+    }
+}
+
+#[derive(Debug)]
+pub struct Contradiction;
+
+/// The set of tiles still possible in every cell of a `rows` by `cols` grid.
+pub struct Wave<'a> {
+    rules: &'a Rules,
+    rows: usize,
+    cols: usize,
+    wrap_rows: bool,
+    wrap_cols: bool,
+    domains: Vec<Vec<bool>>,
+}
+
+impl<'a> Wave<'a> {
+    pub fn new(rules: &'a Rules, rows: usize, cols: usize) -> Self {
+        Self {
+            rules,
+            rows,
+            cols,
+            wrap_rows: false,
+            wrap_cols: false,
+            domains: vec![vec![true; rules.len()]; rows * cols],
+        }
+    }
+
+    /// Makes the bottom row a neighbour of the top row (`wrap_rows`) and the right
+    /// column a neighbour of the left column (`wrap_cols`), so the result tiles.
+    pub fn wrapping(mut self, wrap_rows: bool, wrap_cols: bool) -> Self {
+        self.wrap_rows = wrap_rows;
+        self.wrap_cols = wrap_cols;
+        self
+    }
+
+    fn count(&self, cell: usize) -> usize {
+        self.domains[cell]
+            .iter()
+            .filter(|&&allowed| allowed)
+            .count()
+    }
+
+    fn neighbour(&self, cell: usize, dir: usize) -> Option<usize> {
+        let (row, col) = ((cell / self.cols) as isize, (cell % self.cols) as isize);
+        let (dr, dc) = OFFSETS[dir];
+        let row = wrap(row + dr, self.rows, self.wrap_rows)?;
+        let col = wrap(col + dc, self.cols, self.wrap_cols)?;
+        Some(row * self.cols + col)
+    }
+
+    /// Narrows a cell down to the tiles in `allowed` and propagates the change.
+    pub fn restrict(
+        &mut self,
+        row: usize,
+        col: usize,
+        allowed: &[bool],
+    ) -> Result<(), Contradiction> {
+        let cell = row * self.cols + col;
+        let mut changed = false;
+        for (tile, possible) in self.domains[cell].iter_mut().enumerate() {
+            if *possible && !allowed[tile] {
+                *possible = false;
+                changed = true;
+            }
+        }
+        if self.count(cell) == 0 {
+            return Err(Contradiction);
+        }
+        if changed {
+            self.propagate(vec![cell])?;
+        }
+        Ok(())
+    }
+
+    /// Collapses `row`, `col` to a single tile.
+    pub fn set(&mut self, row: usize, col: usize, tile: usize) -> Result<(), Contradiction> {
+        let mut allowed = vec![false; self.rules.len()];
+        allowed[tile] = true;
+        self.restrict(row, col, &allowed)
+    }
+
+    fn propagate(&mut self, mut stack: Vec<usize>) -> Result<(), Contradiction> {
+        while let Some(cell) = stack.pop() {
+            for dir in 0..4 {
+                let next = match self.neighbour(cell, dir) {
+                    Some(next) => next,
+                    None => continue,
+                };
+                let mut supported = vec![false; self.rules.len()];
+                for a in (0..self.rules.len()).filter(|&a| self.domains[cell][a]) {
+                    for (b, supported) in supported.iter_mut().enumerate() {
+                        *supported |= self.rules.compatible(dir, a, b);
+                    }
+                }
+                let mut changed = false;
+                for (b, possible) in self.domains[next].iter_mut().enumerate() {
+                    if *possible && !supported[b] {
+                        *possible = false;
+                        changed = true;
+                    }
+                }
+                if changed {
+                    if self.count(next) == 0 {
+                        return Err(Contradiction);
+                    }
+                    stack.push(next);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Repeatedly collapses the cell with the fewest remaining tiles until every
+    /// cell holds exactly one.
+    pub fn collapse(&mut self, rng: &mut impl Rng) -> Result<(), Contradiction> {
+        self.collapse_with(rng, |_| {})
+    }
+
+    /// Like `collapse`, calling `observe` before each step.
+    pub fn collapse_with(
+        &mut self,
+        rng: &mut impl Rng,
+        mut observe: impl FnMut(&Self),
+    ) -> Result<(), Contradiction> {
+        loop {
+            observe(self);
+            let mut lowest = usize::MAX;
+            let mut candidates = vec![];
+            for cell in 0..self.domains.len() {
+                let count = self.count(cell);
+                if count <= 1 {
+                    continue;
+                }
+                if count < lowest {
+                    lowest = count;
+                    candidates.clear();
+                }
+                if count == lowest {
+                    candidates.push(cell);
+                }
+            }
+            if candidates.is_empty() {
+                return Ok(());
+            }
+
+            let cell = candidates[rng.gen_range(0, candidates.len())];
+            let options: Vec<usize> = (0..self.rules.len())
+                .filter(|&tile| self.domains[cell][tile])
+                .collect();
+            let tile = options[rng.gen_range(0, options.len())];
+            self.set(cell / self.cols, cell % self.cols, tile)?;
+        }
+    }
+
+    /// The mean over all cells of log2 of the tiles still possible there: zero
+    /// once every cell is collapsed.
+    pub fn entropy(&self) -> f64 {
+        let total: f64 = (0..self.domains.len())
+            .map(|cell| (self.count(cell).max(1) as f64).log2())
+            .sum();
+        total / self.domains.len().max(1) as f64
+    }
+
+    /// The chosen tile of every cell, row by row. Only meaningful after `collapse`.
+    pub fn result(&self) -> Vec<Vec<usize>> {
+        (0..self.rows)
+            .map(|row| {
+                (0..self.cols)
+                    .map(|col| {
+                        let cell = row * self.cols + col;
+                        self.domains[cell].iter().position(|&p| p).unwrap_or(0)
+                    })
+                    .collect()
+            })
+            .collect()
+    }
+}
@@ -1,14 +1,34 @@
+use druid::kurbo::{BezPath, Line};
+use druid::piet::{FontFamily, Text, TextLayoutBuilder};
 use druid::widget::prelude::*;
-use druid::widget::{Button, Flex, Label, Slider};
-use druid::{
-    AppLauncher, Color, Data, Lens, LocalizedString, MouseButton, Point, Rect, TimerToken,
-    WidgetExt, WindowDesc,
-};
+use druid::{Color, Data, MouseButton, Point, Rect};
 
+use std::collections::HashMap;
+
+use crate::border::EdgeKind;
 use crate::data::*;
+use crate::diagnostics::{Diagnostics, ENTROPY_SAMPLES};
+use crate::transform::Transform;
+use crate::wave::Rules;
+use crate::world::{average_color, World, CHUNK_SIZE};
 
 pub struct PaintCanvas {
-    pixel_size: Size,
+    pixel_size: f64,
+    origin: Point,
+    drag: Option<Drag>,
+}
+
+/// What a left-button drag with the select tool is doing.
+enum Drag {
+    /// Stretching a new selection from this corner.
+    Select(CanvasPos),
+    /// Moving the selected pixels: where the drag started, the canvas before the
+    /// move and the selection at that time.
+    Move {
+        from: CanvasPos,
+        original: Box<Canvas>,
+        rect: PixelRect,
+    },
 }
 
 pub struct ViewCanvas {
@@ -26,57 +46,301 @@ impl ViewCanvas {
 impl PaintCanvas {
     pub fn new() -> Self {
         Self {
-            pixel_size: (0., 0.).into(),
+            pixel_size: 0.,
+            origin: Point::ORIGIN,
+            drag: None,
         }
     }
 
-    fn grid_pos(&self, p: Point) -> Option<CanvasPos> {
-        let w0 = self.pixel_size.width;
-        let h0 = self.pixel_size.height;
-        if p.x < 0.0 || p.y < 0.0 || w0 == 0.0 || h0 == 0.0 {
+    /// Scales the canvas to fit `size` with square pixels, centred.
+    fn place(&mut self, size: Size, data: &Canvas) {
+        self.pixel_size = (size.width / data.width as f64)
+            .min(size.height / data.height as f64)
+            .max(1.);
+        self.origin = Point::new(
+            ((size.width - self.pixel_size * data.width as f64) / 2.).floor(),
+            ((size.height - self.pixel_size * data.height as f64) / 2.).floor(),
+        );
+    }
+
+    fn grid_pos(&self, p: Point, data: &Canvas) -> Option<CanvasPos> {
+        let p = p - self.origin.to_vec2();
+        if p.x < 0.0 || p.y < 0.0 || self.pixel_size == 0.0 {
             return None;
         }
-        let row = (p.x / w0) as usize;
-        let col = (p.y / h0) as usize;
-        if row >= CANVAS_SIZE || col >= CANVAS_SIZE {
+        let row = (p.x / self.pixel_size) as usize;
+        let col = (p.y / self.pixel_size) as usize;
+        if row >= data.width || col >= data.height {
             return None;
         }
         Some(CanvasPos { row, col })
     }
+
+    /// The pixel under `p`, or the nearest one when `p` is outside the canvas.
+    fn clamped_grid_pos(&self, p: Point, data: &Canvas) -> CanvasPos {
+        let p = p - self.origin.to_vec2();
+        let row = (p.x / self.pixel_size).max(0.) as usize;
+        let col = (p.y / self.pixel_size).max(0.) as usize;
+        CanvasPos {
+            row: row.min(data.width - 1),
+            col: col.min(data.height - 1),
+        }
+    }
+
+    fn select_event(
+        &mut self,
+        ctx: &mut EventCtx,
+        event: &Event,
+        data: &mut Canvas,
+        view: &mut EditorView,
+    ) {
+        match event {
+            Event::MouseDown(e) if e.button == MouseButton::Left => {
+                let pos = match self.grid_pos(e.pos, data) {
+                    Some(pos) => pos,
+                    None => return,
+                };
+                let selection = view
+                    .selection
+                    .filter(|rect| rect.fits(data.width, data.height) && rect.contains(pos));
+                self.drag = Some(match selection {
+                    Some(rect) => Drag::Move {
+                        from: pos,
+                        original: Box::new(data.clone()),
+                        rect,
+                    },
+                    None => {
+                        view.selection = Some(PixelRect::from_corners(pos, pos));
+                        Drag::Select(pos)
+                    }
+                });
+                ctx.set_active(true);
+            }
+            Event::MouseMove(e) => {
+                let pos = self.clamped_grid_pos(e.pos, data);
+                match &self.drag {
+                    Some(Drag::Select(anchor)) => {
+                        view.selection = Some(PixelRect::from_corners(*anchor, pos));
+                    }
+                    Some(Drag::Move {
+                        from,
+                        original,
+                        rect,
+                    }) => {
+                        let shift = |start: usize, by: isize, max: usize| {
+                            (start as isize + by).max(0).min(max as isize) as usize
+                        };
+                        let x = shift(
+                            rect.x,
+                            pos.row as isize - from.row as isize,
+                            data.width - rect.width,
+                        );
+                        let y = shift(
+                            rect.y,
+                            pos.col as isize - from.col as isize,
+                            data.height - rect.height,
+                        );
+                        let mut moved = (**original).clone();
+                        if (x, y) != (rect.x, rect.y) {
+                            let piece = original.crop(*rect);
+                            moved.fill_rect(*rect, &Color::BLACK);
+                            moved.stamp(&piece, x, y);
+                        }
+                        *data = moved;
+                        view.selection = Some(PixelRect { x, y, ..*rect });
+                    }
+                    None => {}
+                }
+            }
+            Event::MouseUp(_) => {
+                self.drag = None;
+                ctx.set_active(false);
+            }
+            _ => {}
+        }
+    }
+
+    /// Like `grid_pos`, but positions past an edge continue from the opposite edge.
+    fn wrapped_grid_pos(&self, p: Point, data: &Canvas) -> Option<CanvasPos> {
+        if self.pixel_size == 0.0 {
+            return None;
+        }
+        let p = p - self.origin.to_vec2();
+        let row = (p.x / self.pixel_size).floor() as i64;
+        let col = (p.y / self.pixel_size).floor() as i64;
+        Some(CanvasPos {
+            row: row.rem_euclid(data.width as i64) as usize,
+            col: col.rem_euclid(data.height as i64) as usize,
+        })
+    }
+
+    fn paint_lines(&self, ctx: &mut PaintCtx, data: &Canvas, every: usize, color: &Color) {
+        let (width, height) = (
+            self.pixel_size * data.width as f64,
+            self.pixel_size * data.height as f64,
+        );
+        let (x0, y0) = (self.origin.x, self.origin.y);
+        for row in (0..=data.width).step_by(every) {
+            let x = (x0 + self.pixel_size * row as f64).floor() + 0.5;
+            ctx.stroke(Line::new((x, y0), (x, y0 + height)), color, 1.);
+        }
+        for col in (0..=data.height).step_by(every) {
+            let y = (y0 + self.pixel_size * col as f64).floor() + 0.5;
+            ctx.stroke(Line::new((x0, y), (x0 + width, y)), color, 1.);
+        }
+    }
 }
 
-impl Widget<Canvas> for PaintCanvas {
-    fn event(&mut self, ctx: &mut EventCtx, event: &Event, data: &mut Canvas, _env: &Env) {
+impl Widget<(Canvas, EditorView)> for PaintCanvas {
+    fn event(
+        &mut self,
+        ctx: &mut EventCtx,
+        event: &Event,
+        (data, view): &mut (Canvas, EditorView),
+        _env: &Env,
+    ) {
         match event {
             Event::WindowConnected => {
                 ctx.request_paint();
             }
-            Event::MouseDown(e) => {
-                if e.button == MouseButton::Left {
-                    data.drawing = true;
-                    let grid_pos_opt = self.grid_pos(e.pos);
-                    grid_pos_opt
-                        .iter()
-                        .for_each(|pos| data[*pos] = data.current_color.clone());
+            _ if view.tool == Tool::Select => {
+                if let Event::MouseMove(e) = event {
+                    view.hover = self.grid_pos(e.pos, data);
                 }
+                self.select_event(ctx, event, data, view);
             }
-            Event::MouseUp(e) => {
-                if e.button == MouseButton::Left {
-                    data.drawing = false;
+            Event::MouseDown(e) if e.button == MouseButton::Left => {
+                data.drawing = true;
+                ctx.set_active(true);
+                if let Some(pos) = self.grid_pos(e.pos, data) {
+                    data.paint_mirrored(pos, &data.current_color.clone(), view.mirror);
                 }
             }
+            Event::MouseUp(e) if e.button == MouseButton::Left => {
+                data.drawing = false;
+                ctx.set_active(false);
+            }
             Event::MouseMove(e) => {
+                let grid_pos_opt = self.grid_pos(e.pos, data);
                 if data.drawing {
-                    let grid_pos_opt = self.grid_pos(e.pos);
-                    grid_pos_opt
-                        .iter()
-                        .for_each(|pos| data[*pos] = data.current_color.clone());
+                    let pos = if view.wrap {
+                        self.wrapped_grid_pos(e.pos, data)
+                    } else {
+                        grid_pos_opt
+                    };
+                    if let Some(pos) = pos {
+                        data.paint_mirrored(pos, &data.current_color.clone(), view.mirror);
+                    }
                 }
+                view.hover = grid_pos_opt;
             }
             _ => {}
         }
     }
 
+    fn lifecycle(
+        &mut self,
+        _ctx: &mut LifeCycleCtx,
+        _event: &LifeCycle,
+        _data: &(Canvas, EditorView),
+        _env: &Env,
+    ) {
+    }
+
+    fn update(
+        &mut self,
+        ctx: &mut UpdateCtx,
+        _old_data: &(Canvas, EditorView),
+        _data: &(Canvas, EditorView),
+        _env: &Env,
+    ) {
+        ctx.request_paint();
+    }
+
+    fn layout(
+        &mut self,
+        _layout_ctx: &mut LayoutCtx,
+        bc: &BoxConstraints,
+        _data: &(Canvas, EditorView),
+        _env: &Env,
+    ) -> Size {
+        bc.max()
+    }
+
+    fn paint(&mut self, ctx: &mut PaintCtx, (data, view): &(Canvas, EditorView), _env: &Env) {
+        let size: Size = ctx.size();
+        self.place(size, data);
+        let cell_size = Size::new(self.pixel_size, self.pixel_size);
+        for row in 0..data.width {
+            for col in 0..data.height {
+                let pos = CanvasPos { row, col };
+                let point = Point {
+                    x: self.origin.x + self.pixel_size * row as f64,
+                    y: self.origin.y + self.pixel_size * col as f64,
+                };
+                let rect = Rect::from_origin_size(point.floor(), cell_size.ceil());
+                ctx.fill(rect, &data[pos]);
+            }
+        }
+
+        if view.pixel_grid && self.pixel_size >= 4. {
+            self.paint_lines(ctx, data, 1, &Color::grey8(128).with_alpha(0.5));
+        }
+        if view.major_grid {
+            let every = (view.major_every.max(1.)) as usize;
+            self.paint_lines(ctx, data, every, &Color::grey8(64));
+        }
+
+        if let Some(rect) = view.selection {
+            if rect.fits(data.width, data.height) {
+                let rect = Rect::from_origin_size(
+                    (
+                        self.origin.x + self.pixel_size * rect.x as f64,
+                        self.origin.y + self.pixel_size * rect.y as f64,
+                    ),
+                    (
+                        self.pixel_size * rect.width as f64,
+                        self.pixel_size * rect.height as f64,
+                    ),
+                );
+                ctx.stroke(rect, &Color::BLACK, 3.);
+                ctx.stroke(rect, &Color::WHITE, 1.);
+            }
+        }
+
+        if let Some(pos) = view.hover {
+            if pos.row < data.width && pos.col < data.height {
+                let x = self.origin.x + self.pixel_size * pos.row as f64;
+                let y = self.origin.y + self.pixel_size * pos.col as f64;
+                let (width, height) = (
+                    self.pixel_size * data.width as f64,
+                    self.pixel_size * data.height as f64,
+                );
+                let shade = Color::rgba8(255, 255, 255, 40);
+                let column = Rect::new(
+                    x,
+                    self.origin.y,
+                    x + self.pixel_size,
+                    self.origin.y + height,
+                );
+                let row = Rect::new(self.origin.x, y, self.origin.x + width, y + self.pixel_size);
+                ctx.fill(column, &shade);
+                ctx.fill(row, &shade);
+                let pixel = Rect::from_origin_size((x, y), cell_size);
+                ctx.stroke(pixel.inset(-1.), &Color::BLACK, 2.);
+                ctx.stroke(pixel, &Color::WHITE, 1.);
+            }
+        }
+    }
+}
+
+impl Widget<Canvas> for ViewCanvas {
+    fn event(&mut self, ctx: &mut EventCtx, event: &Event, _data: &mut Canvas, _env: &Env) {
+        if let Event::WindowConnected = event {
+            ctx.request_paint();
+        }
+    }
+
     fn lifecycle(
         &mut self,
         _ctx: &mut LifeCycleCtx,
@@ -107,15 +371,15 @@ impl Widget<Canvas> for PaintCanvas {
 
     fn paint(&mut self, ctx: &mut PaintCtx, data: &Canvas, _env: &Env) {
         let size: Size = ctx.size();
-        let w0 = size.width / CANVAS_SIZE as f64;
-        let h0 = size.height / CANVAS_SIZE as f64;
+        let w0 = size.width / data.width as f64;
+        let h0 = size.height / data.height as f64;
         let cell_size = Size {
             width: w0,
             height: h0,
         };
         self.pixel_size = cell_size;
-        for row in 0..CANVAS_SIZE {
-            for col in 0..CANVAS_SIZE {
+        for row in 0..data.width {
+            for col in 0..data.height {
                 let pos = CanvasPos { row, col };
                 let point = Point {
                     x: w0 * row as f64,
@@ -128,26 +392,722 @@ impl Widget<Canvas> for PaintCanvas {
     }
 }
 
-impl Widget<Canvas> for ViewCanvas {
-    fn event(&mut self, ctx: &mut EventCtx, event: &Event, _data: &mut Canvas, _env: &Env) {
+const RULER_SIZE: f64 = 18.;
+/// How many chunks of the endless map are generated per animation frame.
+const CHUNKS_PER_FRAME: usize = 2;
+/// How many chunks beyond the view the endless map keeps before forgetting them.
+const KEPT_CHUNKS: i64 = 4;
+
+pub struct MapCanvas {
+    cell_size: f64,
+    ruler_size: f64,
+    /// Where cell (0, 0) is drawn. In the tiled preview this is the top-left copy.
+    grid_origin: Point,
+    /// Where the editable copy of the map is drawn; only differs from `grid_origin`
+    /// in the tiled preview, which draws it in the middle.
+    map_origin: Point,
+    painting: bool,
+    selection_anchor: Option<(usize, usize)>,
+    pan_from: Option<Point>,
+    variants: HashMap<(usize, Transform), Canvas>,
+    world: Option<World>,
+    tile_colors: Vec<Color>,
+}
+
+impl MapCanvas {
+    pub fn new() -> Self {
+        Self {
+            cell_size: 0.,
+            ruler_size: 0.,
+            grid_origin: Point::ORIGIN,
+            map_origin: Point::ORIGIN,
+            painting: false,
+            selection_anchor: None,
+            pan_from: None,
+            variants: HashMap::new(),
+            world: None,
+            tile_colors: vec![],
+        }
+    }
+
+    fn map_dims(data: &AppState) -> (usize, usize) {
+        let rows = data.map.len().max(1);
+        let cols = data
+            .map
+            .iter()
+            .map(|row| row.len())
+            .max()
+            .unwrap_or(0)
+            .max(1);
+        (rows, cols)
+    }
+
+    fn copies(data: &AppState) -> usize {
+        if data.tiled_preview && !data.infinite_map {
+            3
+        } else {
+            1
+        }
+    }
+
+    /// Works out the cell size and origins for the current view and widget size.
+    fn place(&mut self, size: Size, data: &AppState) {
+        self.ruler_size = if data.show_rulers { RULER_SIZE } else { 0. };
+        let ruler = self.ruler_size;
+        let view = data.map_view;
+        let (rows, cols) = Self::map_dims(data);
+        let copies = Self::copies(data);
+
+        if view.fit && !data.infinite_map {
+            self.cell_size = ((size.width - ruler) / (cols * copies) as f64)
+                .min((size.height - ruler) / (rows * copies) as f64)
+                .max(1.);
+            self.grid_origin = Point::new(ruler, ruler);
+        } else {
+            self.cell_size = view.cell_size;
+            self.grid_origin = Point::new(
+                ruler - view.x * view.cell_size,
+                ruler - view.y * view.cell_size,
+            );
+        }
+        let middle = (copies / 2) as f64;
+        self.map_origin = Point::new(
+            self.grid_origin.x + middle * self.cell_size * cols as f64,
+            self.grid_origin.y + middle * self.cell_size * rows as f64,
+        );
+    }
+
+    /// Leaves fit-to-window mode, keeping what is currently on screen.
+    fn unfit(&self, data: &mut AppState) {
+        let view = &mut data.map_view;
+        if view.fit && self.cell_size > 0. {
+            view.fit = false;
+            view.cell_size = self.cell_size;
+            view.x = (self.ruler_size - self.grid_origin.x) / self.cell_size;
+            view.y = (self.ruler_size - self.grid_origin.y) / self.cell_size;
+        }
+    }
+
+    fn pan(&mut self, to: Point, data: &mut AppState) {
+        if let Some(from) = self.pan_from {
+            self.unfit(data);
+            let view = &mut data.map_view;
+            view.x -= (to.x - from.x) / view.cell_size;
+            view.y -= (to.y - from.y) / view.cell_size;
+            self.pan_from = Some(to);
+        }
+    }
+
+    /// Zooms in or out around `p`, keeping the cell under it in place.
+    fn zoom(&mut self, p: Point, zoom_in: bool, data: &mut AppState) {
+        self.unfit(data);
+        let ruler = self.ruler_size;
+        let view = &mut data.map_view;
+        let (wx, wy) = (
+            view.x + (p.x - ruler) / view.cell_size,
+            view.y + (p.y - ruler) / view.cell_size,
+        );
+        let factor = if zoom_in { 1.25 } else { 0.8 };
+        view.cell_size = (view.cell_size * factor).clamp(2., 128.);
+        view.x = wx - (p.x - ruler) / view.cell_size;
+        view.y = wy - (p.y - ruler) / view.cell_size;
+    }
+
+    fn world(&mut self, data: &AppState) -> &mut World {
+        if self.world.is_none() {
+            let rules = Rules::build(&data.modules, &data.adjacency);
+            let world = World::new(rules, data.world_seed);
+            self.tile_colors = world
+                .rules
+                .tiles
+                .iter()
+                .map(|tile| average_color(&tile.canvas))
+                .collect();
+            self.world = Some(world);
+        }
+        self.world.as_mut().unwrap()
+    }
+
+    fn world_pos(&self, p: Point) -> (i64, i64) {
+        let x = ((p.x - self.grid_origin.x) / self.cell_size).floor() as i64;
+        let y = ((p.y - self.grid_origin.y) / self.cell_size).floor() as i64;
+        (x, y)
+    }
+
+    /// The cell under `p`, or the nearest one when `p` is outside the map.
+    fn clamped_cell_pos(&self, p: Point, data: &AppState) -> Option<(usize, usize)> {
+        if self.cell_size == 0.0 || data.map.is_empty() {
+            return None;
+        }
+        let max_row = data.map.len() - 1;
+        let max_col = data.map[0].len().max(1) - 1;
+        let p = p - self.map_origin.to_vec2();
+        let row = (p.y / self.cell_size).max(0.) as usize;
+        let col = (p.x / self.cell_size).max(0.) as usize;
+        Some((row.min(max_row), col.min(max_col)))
+    }
+
+    /// The cell under `p`. In the tiled preview every copy maps back onto the map,
+    /// while clicks beside the copies hit nothing.
+    fn cell_pos(&self, p: Point, data: &AppState) -> Option<(usize, usize)> {
+        let p = p - self.grid_origin.to_vec2();
+        if p.x < 0.0 || p.y < 0.0 || self.cell_size == 0.0 || data.map.is_empty() {
+            return None;
+        }
+        let mut row = (p.y / self.cell_size) as usize;
+        let mut col = (p.x / self.cell_size) as usize;
+        if data.tiled_preview {
+            let (rows, cols) = Self::map_dims(data);
+            let copies = Self::copies(data);
+            if row >= rows * copies || col >= cols * copies {
+                return None;
+            }
+            row %= data.map.len();
+            col %= data.map[row].len();
+        }
+        if row >= data.map.len() || col >= data.map[row].len() {
+            return None;
+        }
+        Some((row, col))
+    }
+
+    fn variant(&mut self, data: &AppState, cell: &MapCell) -> Option<&Canvas> {
+        let index = data.find_index(cell.module_id)?;
+        let key = (cell.module_id, cell.transform);
+        let module = &data.modules[index];
+        Some(
+            self.variants
+                .entry(key)
+                .or_insert_with(|| cell.transform.apply(module)),
+        )
+    }
+
+    fn world_event(&mut self, ctx: &mut EventCtx, event: &Event, data: &mut AppState) {
+        match event {
+            Event::MouseDown(e) if e.button == MouseButton::Left && e.mods.shift() => {
+                let (x, y) = self.world_pos(e.pos);
+                let world = self.world(data);
+                if let Some(tile) = world.cell(x, y) {
+                    let module_id = world.rules.tiles[tile].module_id;
+                    data.pick_module(module_id);
+                }
+            }
+            Event::MouseDown(e) if e.button == MouseButton::Left => {
+                self.pan_from = Some(e.pos);
+                ctx.set_active(true);
+            }
+            _ => {}
+        }
+    }
+
+    fn map_event(&mut self, ctx: &mut EventCtx, event: &Event, data: &mut AppState) {
+        match event {
+            Event::MouseDown(e) => {
+                let (row, col) = match self.cell_pos(e.pos, data) {
+                    Some(pos) => pos,
+                    None => return,
+                };
+                if e.button == MouseButton::Left && e.mods.shift() {
+                    data.pick_map_cell(row, col);
+                } else if e.button == MouseButton::Left && e.mods.ctrl() {
+                    self.selection_anchor = Some((row, col));
+                    ctx.set_active(true);
+                    data.map_selection = Some(MapRect::from_corners((row, col), (row, col)));
+                } else if e.button == MouseButton::Left {
+                    self.painting = true;
+                    ctx.set_active(true);
+                    data.push_undo();
+                    data.paint_map_cell(row, col);
+                } else if e.button == MouseButton::Right && e.mods.ctrl() {
+                    data.push_undo();
+                    data.toggle_pin(row, col);
+                } else if e.button == MouseButton::Right && e.mods.shift() {
+                    data.push_undo();
+                    data.flip_map_cell(row, col);
+                } else if e.button == MouseButton::Right {
+                    data.push_undo();
+                    data.rotate_map_cell(row, col);
+                }
+            }
+            Event::MouseMove(e) => {
+                if self.painting {
+                    if let Some((row, col)) = self.cell_pos(e.pos, data) {
+                        data.paint_map_cell(row, col);
+                    }
+                } else if let Some(anchor) = self.selection_anchor {
+                    if let Some(pos) = self.clamped_cell_pos(e.pos, data) {
+                        data.map_selection = Some(MapRect::from_corners(anchor, pos));
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// The first and last chunks of the endless map that are in view.
+    fn visible_chunks(&self, size: Size) -> ((i64, i64), (i64, i64)) {
+        let (x0, y0) = self.world_pos(Point::ORIGIN);
+        let (x1, y1) = self.world_pos(Point::new(size.width, size.height));
+        (
+            (x0.div_euclid(CHUNK_SIZE), y0.div_euclid(CHUNK_SIZE)),
+            (x1.div_euclid(CHUNK_SIZE), y1.div_euclid(CHUNK_SIZE)),
+        )
+    }
+
+    /// Generates a few of the missing chunks in view, nearest the middle first, and
+    /// forgets the ones far outside it. Runs every animation frame until the view
+    /// is filled, so painting never waits on the solver.
+    fn generate_chunks(&mut self, ctx: &mut EventCtx, data: &AppState) {
+        self.place(ctx.size(), data);
+        let ((cx0, cy0), (cx1, cy1)) = self.visible_chunks(ctx.size());
+        let world = self.world(data);
+        let mut missing: Vec<(i64, i64)> = (cy0..=cy1)
+            .flat_map(|cy| (cx0..=cx1).map(move |cx| (cx, cy)))
+            .filter(|&(cx, cy)| !world.has_chunk(cx, cy))
+            .collect();
+        let middle = ((cx0 + cx1) / 2, (cy0 + cy1) / 2);
+        missing.sort_by_key(|&(cx, cy)| (cx - middle.0).abs() + (cy - middle.1).abs());
+        for &(cx, cy) in missing.iter().take(CHUNKS_PER_FRAME) {
+            world.generate(cx, cy);
+        }
+        world.retain(|cx, cy| {
+            cx >= cx0 - KEPT_CHUNKS
+                && cx <= cx1 + KEPT_CHUNKS
+                && cy >= cy0 - KEPT_CHUNKS
+                && cy <= cy1 + KEPT_CHUNKS
+        });
+        if missing.len() > CHUNKS_PER_FRAME {
+            ctx.request_anim_frame();
+        }
+        ctx.request_paint();
+    }
+
+    /// Draws the generated chunks in view; the rest are left blank until
+    /// `generate_chunks` gets to them.
+    fn paint_world(&mut self, ctx: &mut PaintCtx) {
+        let world = match &self.world {
+            Some(world) => world,
+            None => return,
+        };
+        let size = ctx.size();
+        let cell_size = self.cell_size;
+        let origin = self.grid_origin;
+        let (x0, y0) = self.world_pos(Point::ORIGIN);
+        let (x1, y1) = self.world_pos(Point::new(size.width, size.height));
+
+        for y in y0..=y1 {
+            for x in x0..=x1 {
+                let point = Point {
+                    x: (origin.x + x as f64 * cell_size).floor(),
+                    y: (origin.y + y as f64 * cell_size).floor(),
+                };
+                let rect = Rect::from_origin_size(point, (cell_size, cell_size)).ceil();
+                match world.generated_cell(x, y) {
+                    Some(Some(tile)) => {
+                        let canvas = &world.rules.tiles[tile].canvas;
+                        if cell_size < canvas.width as f64 {
+                            ctx.fill(rect, &self.tile_colors[tile]);
+                        } else {
+                            paint_pixels(ctx, canvas, point, cell_size);
+                        }
+                    }
+                    Some(None) => ctx.fill(rect, &Color::BLACK),
+                    None => {}
+                }
+            }
+        }
+    }
+
+    fn paint_map(&mut self, ctx: &mut PaintCtx, data: &AppState) {
+        let cell_size = self.cell_size;
+        let (rows, cols) = Self::map_dims(data);
+        let copies = Self::copies(data);
+        let map_size = Size::new(cell_size * cols as f64, cell_size * rows as f64);
+
+        for copy_row in 0..copies {
+            for copy_col in 0..copies {
+                let offset = (
+                    self.grid_origin.x + map_size.width * copy_col as f64,
+                    self.grid_origin.y + map_size.height * copy_row as f64,
+                );
+                for (row, cells) in data.map.iter().enumerate() {
+                    for (col, cell) in cells.iter().enumerate() {
+                        let origin = Point {
+                            x: offset.0 + cell_size * col as f64,
+                            y: offset.1 + cell_size * row as f64,
+                        };
+                        let rect = Rect::from_origin_size(origin, (cell_size, cell_size));
+                        match self.variant(data, cell) {
+                            Some(canvas) => paint_pixels(ctx, canvas, origin, cell_size),
+                            None => ctx.fill(rect, &Color::BLACK),
+                        }
+                    }
+                }
+            }
+        }
+
+        let origin = self.map_origin;
+        if data.tiled_preview {
+            ctx.stroke(
+                Rect::from_origin_size(origin, map_size),
+                &Color::grey(0.8),
+                1.,
+            );
+        }
+        for (row, cells) in data.map.iter().enumerate() {
+            for (col, cell) in cells.iter().enumerate() {
+                if cell.pinned {
+                    let rect = Rect::from_origin_size(
+                        (
+                            origin.x + cell_size * col as f64,
+                            origin.y + cell_size * row as f64,
+                        ),
+                        (cell_size, cell_size),
+                    );
+                    ctx.stroke(rect.inset(-1.), &Color::rgb8(255, 128, 0), 2.);
+                }
+            }
+        }
+
+        if let Some(selection) = data.map_selection {
+            let rect = Rect::from_origin_size(
+                (
+                    origin.x + cell_size * selection.col as f64,
+                    origin.y + cell_size * selection.row as f64,
+                ),
+                (
+                    cell_size * selection.cols as f64,
+                    cell_size * selection.rows as f64,
+                ),
+            );
+            ctx.fill(rect, &Color::rgba8(255, 255, 255, 48));
+            ctx.stroke(rect.inset(-1.), &Color::WHITE, 2.);
+        }
+        if !data.tiled_preview && !data.periodic_map {
+            self.paint_border(ctx, data);
+        }
+    }
+
+    /// A dimmed row of cells outside each map edge showing what the border
+    /// settings allow there: the first listed module, or the virtual socket.
+    fn paint_border(&self, ctx: &mut PaintCtx, data: &AppState) {
+        let cell_size = self.cell_size;
+        let origin = self.map_origin;
+        let (rows, cols) = Self::map_dims(data);
+        for dir in 0..4 {
+            let edge = data.map_border.edge(dir);
+            let cells: Vec<(isize, isize)> = match dir {
+                0 => (0..cols as isize).map(|col| (-1, col)).collect(),
+                1 => (0..rows as isize).map(|row| (row, cols as isize)).collect(),
+                2 => (0..cols as isize).map(|col| (rows as isize, col)).collect(),
+                _ => (0..rows as isize).map(|row| (row, -1)).collect(),
+            };
+            let module = edge
+                .module_names()
+                .first()
+                .and_then(|key| data.modules.iter().find(|module| module.key() == *key));
+            for &(row, col) in &cells {
+                let point = Point::new(
+                    origin.x + cell_size * col as f64,
+                    origin.y + cell_size * row as f64,
+                );
+                let rect = Rect::from_origin_size(point, (cell_size, cell_size));
+                match (edge.kind, module) {
+                    (EdgeKind::Any, _) => continue,
+                    (EdgeKind::Modules, Some(module)) => {
+                        paint_pixels(ctx, module, point, cell_size);
+                        ctx.fill(rect, &Color::rgba8(0, 0, 0, 128));
+                    }
+                    (EdgeKind::Modules, None) => ctx.fill(rect, &Color::rgba8(255, 64, 64, 96)),
+                    (EdgeKind::Socket, _) => ctx.fill(rect, &Color::rgba8(80, 160, 255, 96)),
+                }
+            }
+            if edge.kind == EdgeKind::Socket && cell_size >= 12. {
+                let &(row, col) = cells.first().unwrap();
+                let at = Point::new(
+                    origin.x + cell_size * col as f64 + 2.,
+                    origin.y + cell_size * row as f64 + 2.,
+                );
+                paint_label(ctx, edge.socket.label.trim(), at);
+            }
+        }
+    }
+
+    /// Lines between cells, across the whole view for the infinite map and across
+    /// the map (and its copies) otherwise.
+    fn paint_grid(&self, ctx: &mut PaintCtx, data: &AppState) {
+        if self.cell_size < 4. {
+            return;
+        }
+        let size = ctx.size();
+        let bounds = if data.infinite_map {
+            size.to_rect()
+        } else {
+            let (rows, cols) = Self::map_dims(data);
+            let copies = Self::copies(data) as f64;
+            Rect::from_origin_size(
+                self.grid_origin,
+                (
+                    self.cell_size * cols as f64 * copies,
+                    self.cell_size * rows as f64 * copies,
+                ),
+            )
+            .intersect(size.to_rect())
+        };
+        let color = Color::rgba8(255, 255, 255, 64);
+        let first = |start: f64, origin: f64| {
+            origin + ((start - origin) / self.cell_size).ceil() * self.cell_size
+        };
+
+        let mut x = first(bounds.x0, self.grid_origin.x);
+        while x <= bounds.x1 {
+            let line = Line::new((x.floor(), bounds.y0), (x.floor(), bounds.y1));
+            ctx.stroke(line, &color, 1.);
+            x += self.cell_size;
+        }
+        let mut y = first(bounds.y0, self.grid_origin.y);
+        while y <= bounds.y1 {
+            let line = Line::new((bounds.x0, y.floor()), (bounds.x1, y.floor()));
+            ctx.stroke(line, &color, 1.);
+            y += self.cell_size;
+        }
+    }
+
+    /// Cell coordinates along the top and left edges, counted from the editable map
+    /// (or world cell 0).
+    fn paint_rulers(&self, ctx: &mut PaintCtx) {
+        let size = ctx.size();
+        let ruler = self.ruler_size;
+        let background = Color::grey(0.9);
+        ctx.fill(Rect::new(0., 0., size.width, ruler), &background);
+        ctx.fill(Rect::new(0., 0., ruler, size.height), &background);
+
+        let step = [1, 2, 5, 10, 20, 50, 100, 200, 500, 1000]
+            .iter()
+            .copied()
+            .find(|&step| step as f64 * self.cell_size >= 28.)
+            .unwrap_or(1000);
+        let origin = self.map_origin;
+        let first = |start: f64, origin: f64| {
+            let cell = ((start - origin) / self.cell_size).floor() as i64;
+            cell - cell.rem_euclid(step)
+        };
+
+        let mut col = first(ruler, origin.x);
+        loop {
+            let x = origin.x + col as f64 * self.cell_size;
+            if x > size.width {
+                break;
+            }
+            if x >= ruler {
+                ctx.stroke(Line::new((x, ruler - 4.), (x, ruler)), &Color::BLACK, 1.);
+                paint_label(ctx, &col.to_string(), Point::new(x + 2., 2.));
+            }
+            col += step;
+        }
+        let mut row = first(ruler, origin.y);
+        loop {
+            let y = origin.y + row as f64 * self.cell_size;
+            if y > size.height {
+                break;
+            }
+            if y >= ruler {
+                ctx.stroke(Line::new((ruler - 4., y), (ruler, y)), &Color::BLACK, 1.);
+                paint_label(ctx, &row.to_string(), Point::new(2., y + 2.));
+            }
+            row += step;
+        }
+        ctx.fill(Rect::new(0., 0., ruler, ruler), &background);
+    }
+}
+
+impl Widget<AppState> for MapCanvas {
+    fn event(&mut self, ctx: &mut EventCtx, event: &Event, data: &mut AppState, _env: &Env) {
         match event {
             Event::WindowConnected => {
                 ctx.request_paint();
+                ctx.request_anim_frame();
             }
-            _ => {}
+            Event::AnimFrame(_) if data.infinite_map => self.generate_chunks(ctx, data),
+            Event::Wheel(e) => {
+                self.zoom(e.pos, e.wheel_delta.y < 0., data);
+                ctx.set_handled();
+            }
+            Event::MouseDown(e) if e.button == MouseButton::Middle => {
+                self.pan_from = Some(e.pos);
+                ctx.set_active(true);
+            }
+            Event::MouseUp(_) => {
+                self.painting = false;
+                self.selection_anchor = None;
+                self.pan_from = None;
+                ctx.set_active(false);
+            }
+            Event::MouseMove(e) if self.pan_from.is_some() => {
+                self.pan(e.pos, data);
+            }
+            _ if data.infinite_map => self.world_event(ctx, event, data),
+            _ => self.map_event(ctx, event, data),
+        }
+    }
+
+    fn lifecycle(
+        &mut self,
+        ctx: &mut LifeCycleCtx,
+        event: &LifeCycle,
+        _data: &AppState,
+        _env: &Env,
+    ) {
+        if let LifeCycle::Size(_) = event {
+            ctx.request_anim_frame();
+        }
+    }
+
+    fn update(&mut self, ctx: &mut UpdateCtx, old_data: &AppState, data: &AppState, _env: &Env) {
+        if !old_data.modules.same(&data.modules) {
+            self.variants.clear();
+            self.world = None;
+        }
+        if old_data.world_seed != data.world_seed || !old_data.adjacency.same(&data.adjacency) {
+            self.world = None;
         }
+        if data.infinite_map {
+            ctx.request_anim_frame();
+        }
+        ctx.request_paint();
+    }
+
+    fn layout(
+        &mut self,
+        _layout_ctx: &mut LayoutCtx,
+        bc: &BoxConstraints,
+        _data: &AppState,
+        _env: &Env,
+    ) -> Size {
+        bc.max()
+    }
+
+    fn paint(&mut self, ctx: &mut PaintCtx, data: &AppState, _env: &Env) {
+        let size: Size = ctx.size();
+        ctx.clip(size.to_rect());
+        ctx.fill(size.to_rect(), &Color::grey(0.2));
+        self.place(size, data);
+        if data.infinite_map {
+            self.paint_world(ctx);
+        } else {
+            self.paint_map(ctx, data);
+        }
+        if data.show_grid {
+            self.paint_grid(ctx, data);
+        }
+        if data.show_rulers {
+            self.paint_rulers(ctx);
+        }
+    }
+}
+
+fn paint_label(ctx: &mut PaintCtx, text: &str, at: Point) {
+    let layout = ctx
+        .text()
+        .new_text_layout(text.to_string())
+        .font(FontFamily::SYSTEM_UI, 9.)
+        .text_color(Color::BLACK)
+        .build()
+        .unwrap();
+    ctx.draw_text(&layout, at);
+}
+
+/// Draws `canvas` pixel by pixel into a `cell_size` square at `origin`.
+fn paint_pixels(ctx: &mut PaintCtx, canvas: &Canvas, origin: Point, cell_size: f64) {
+    let w0 = cell_size / canvas.width as f64;
+    let h0 = cell_size / canvas.height as f64;
+    let pixel_size = Size {
+        width: w0,
+        height: h0,
+    };
+    for row in 0..canvas.width {
+        for col in 0..canvas.height {
+            let pos = CanvasPos { row, col };
+            let point = Point {
+                x: origin.x + w0 * row as f64,
+                y: origin.y + h0 * col as f64,
+            };
+            let rect = Rect::from_origin_size(point.floor(), pixel_size.ceil());
+            ctx.fill(rect, &canvas[pos]);
+        }
+    }
+}
+
+/// The active module tiled 3x3, or surrounded by modules generation may place next to it.
+pub struct TilePreview {
+    rules: Option<Rules>,
+}
+
+impl TilePreview {
+    pub fn new() -> Self {
+        Self { rules: None }
     }
 
+    /// Picks a tile for each of the eight cells around the active module, in
+    /// reading order with the centre skipped. Edge cells must match the centre;
+    /// corner cells must match both edge cells next to them.
+    fn neighbours(&mut self, data: &AppState) -> Vec<Option<Canvas>> {
+        let rules = self
+            .rules
+            .get_or_insert_with(|| Rules::build(&data.modules, &data.adjacency));
+        let center = match rules.tile_index(data.active_canvas_id, Transform::IDENTITY) {
+            Some(center) => center,
+            None => return vec![None; 8],
+        };
+        let find = |constraints: &[(usize, usize)]| {
+            (0..rules.len()).find(|&tile| {
+                constraints
+                    .iter()
+                    .all(|&(dir, from)| rules.compatible(dir, from, tile))
+            })
+        };
+
+        // Up, right, down, left of the centre.
+        let up = find(&[(0, center)]);
+        let right = find(&[(1, center)]);
+        let down = find(&[(2, center)]);
+        let left = find(&[(3, center)]);
+        let corner = |vertical: Option<usize>, v_dir, horizontal: Option<usize>, h_dir| {
+            find(&[(h_dir, vertical?), (v_dir, horizontal?)])
+        };
+        let cells = [
+            corner(up, 0, left, 3),
+            up,
+            corner(up, 0, right, 1),
+            left,
+            right,
+            corner(down, 2, left, 3),
+            down,
+            corner(down, 2, right, 1),
+        ];
+        cells
+            .iter()
+            .map(|tile| tile.map(|tile| rules.tiles[tile].canvas.clone()))
+            .collect()
+    }
+}
+
+impl Widget<AppState> for TilePreview {
+    fn event(&mut self, _ctx: &mut EventCtx, _event: &Event, _data: &mut AppState, _env: &Env) {}
+
     fn lifecycle(
         &mut self,
         _ctx: &mut LifeCycleCtx,
         _event: &LifeCycle,
-        _data: &Canvas,
+        _data: &AppState,
         _env: &Env,
     ) {
     }
 
-    fn update(&mut self, ctx: &mut UpdateCtx, _old_data: &Canvas, _data: &Canvas, _env: &Env) {
+    fn update(&mut self, ctx: &mut UpdateCtx, old_data: &AppState, data: &AppState, _env: &Env) {
+        if !old_data.modules.same(&data.modules) || !old_data.adjacency.same(&data.adjacency) {
+            self.rules = None;
+        }
         ctx.request_paint();
     }
 
@@ -155,7 +1115,7 @@ impl Widget<Canvas> for ViewCanvas {
         &mut self,
         _layout_ctx: &mut LayoutCtx,
         bc: &BoxConstraints,
-        _data: &Canvas,
+        _data: &AppState,
         _env: &Env,
     ) -> Size {
         let max_size = bc.max();
@@ -166,25 +1126,313 @@ impl Widget<Canvas> for ViewCanvas {
         }
     }
 
-    fn paint(&mut self, ctx: &mut PaintCtx, data: &Canvas, _env: &Env) {
+    fn paint(&mut self, ctx: &mut PaintCtx, data: &AppState, _env: &Env) {
         let size: Size = ctx.size();
-        let w0 = size.width / CANVAS_SIZE as f64;
-        let h0 = size.height / CANVAS_SIZE as f64;
-        let cell_size = Size {
-            width: w0,
-            height: h0,
+        ctx.fill(size.to_rect(), &Color::grey(0.2));
+        let cell_size = (size.width.min(size.height) / 3.).floor();
+        let active = data.get_active_module();
+        let around = if data.preview_neighbours {
+            self.neighbours(data)
+        } else {
+            vec![Some(active.clone()); 8]
         };
-        self.pixel_size = cell_size;
-        for row in 0..CANVAS_SIZE {
-            for col in 0..CANVAS_SIZE {
-                let pos = CanvasPos { row, col };
-                let point = Point {
-                    x: w0 * row as f64,
-                    y: h0 * col as f64,
+
+        let mut around = around.into_iter();
+        for row in 0..3 {
+            for col in 0..3 {
+                let origin = Point::new(cell_size * col as f64, cell_size * row as f64);
+                let canvas = if (row, col) == (1, 1) {
+                    Some(active.clone())
+                } else {
+                    around.next().unwrap()
                 };
-                let rect = Rect::from_origin_size(point.floor(), cell_size.ceil());
-                ctx.fill(rect, &data[pos]);
+                if let Some(canvas) = canvas {
+                    paint_pixels(ctx, &canvas, origin, cell_size);
+                }
+            }
+        }
+        if data.preview_neighbours {
+            let center = Rect::from_origin_size((cell_size, cell_size), (cell_size, cell_size));
+            ctx.stroke(center, &Color::grey(0.8), 1.);
+        }
+    }
+}
+
+const RULE_HEADER: f64 = 28.;
+const RULE_COUNT_WIDTH: f64 = 32.;
+
+/// A module-by-module grid of the hand-edited adjacency rules for one direction.
+/// Each row is a module and each column a neighbour it may have in that direction;
+/// clicking a cell toggles it. Dots mark pairs whose edges match.
+pub struct RuleMatrix {
+    cell_size: f64,
+    derived: Option<Rules>,
+}
+
+impl RuleMatrix {
+    pub fn new() -> Self {
+        Self {
+            cell_size: 0.,
+            derived: None,
+        }
+    }
+
+    fn cell_at(&self, p: Point, data: &AppState) -> Option<(usize, usize)> {
+        if self.cell_size == 0. || p.x < RULE_HEADER || p.y < RULE_HEADER {
+            return None;
+        }
+        let row = ((p.y - RULE_HEADER) / self.cell_size) as usize;
+        let col = ((p.x - RULE_HEADER) / self.cell_size) as usize;
+        if row >= data.modules.len() || col >= data.modules.len() {
+            return None;
+        }
+        Some((row, col))
+    }
+
+    /// Whether the edges alone would let `to` lie next to `from`. This ignores the
+    /// hand-edited rules on purpose: the dots show what they override.
+    fn edges_match(&mut self, data: &AppState, from: &Canvas, to: &Canvas) -> bool {
+        let rules = self
+            .derived
+            .get_or_insert_with(|| Rules::from_modules(&data.modules));
+        let tiles = (
+            rules.tile_index(from.id, Transform::IDENTITY),
+            rules.tile_index(to.id, Transform::IDENTITY),
+        );
+        match tiles {
+            (Some(a), Some(b)) => rules.compatible(data.rule_direction, a, b),
+            _ => false,
+        }
+    }
+}
+
+impl Widget<AppState> for RuleMatrix {
+    fn event(&mut self, ctx: &mut EventCtx, event: &Event, data: &mut AppState, _env: &Env) {
+        if let Event::MouseDown(e) = event {
+            if let Some((row, col)) = self.cell_at(e.pos, data) {
+                let from = data.modules[row].key();
+                let to = data.modules[col].key();
+                data.adjacency.toggle(&from, data.rule_direction, &to);
+                ctx.request_paint();
+            }
+        }
+    }
+
+    fn lifecycle(
+        &mut self,
+        _ctx: &mut LifeCycleCtx,
+        _event: &LifeCycle,
+        _data: &AppState,
+        _env: &Env,
+    ) {
+    }
+
+    fn update(&mut self, ctx: &mut UpdateCtx, old_data: &AppState, data: &AppState, _env: &Env) {
+        if !old_data.modules.same(&data.modules) {
+            self.derived = None;
+        }
+        ctx.request_paint();
+    }
+
+    fn layout(
+        &mut self,
+        _layout_ctx: &mut LayoutCtx,
+        bc: &BoxConstraints,
+        _data: &AppState,
+        _env: &Env,
+    ) -> Size {
+        bc.max()
+    }
+
+    fn paint(&mut self, ctx: &mut PaintCtx, data: &AppState, _env: &Env) {
+        let size = ctx.size();
+        ctx.clip(size.to_rect());
+        let n = data.modules.len().max(1) as f64;
+        self.cell_size = ((size.width - RULE_HEADER - RULE_COUNT_WIDTH) / n)
+            .min((size.height - RULE_HEADER) / n)
+            .clamp(4., 32.)
+            .floor();
+        let cell_size = self.cell_size;
+        let thumb = (RULE_HEADER - 4.).min(cell_size - 2.).max(2.);
+
+        for (i, module) in data.modules.iter().enumerate() {
+            let offset = RULE_HEADER + cell_size * i as f64 + (cell_size - thumb) / 2.;
+            paint_pixels(ctx, module, Point::new(2., offset), thumb);
+            paint_pixels(ctx, module, Point::new(offset, 2.), thumb);
+        }
+
+        let direction = data.rule_direction;
+        let modules: Vec<Canvas> = data.modules.iter().cloned().collect();
+        for (row, from) in modules.iter().enumerate() {
+            let y = RULE_HEADER + cell_size * row as f64;
+            for (col, to) in modules.iter().enumerate() {
+                let x = RULE_HEADER + cell_size * col as f64;
+                let rect = Rect::from_origin_size((x, y), (cell_size, cell_size)).inset(-1.);
+                let color = if data.adjacency.allows(&from.key(), direction, &to.key()) {
+                    Color::rgb8(60, 170, 90)
+                } else {
+                    Color::grey(0.85)
+                };
+                ctx.fill(rect, &color);
+                if self.edges_match(data, from, to) {
+                    let dot = Rect::from_center_size(rect.center(), (3., 3.));
+                    ctx.fill(dot, &Color::BLACK);
+                }
+            }
+            let count = data.adjacency.count(&from.key(), direction);
+            let x = RULE_HEADER + cell_size * modules.len() as f64 + 4.;
+            paint_label(
+                ctx,
+                &count.to_string(),
+                Point::new(x, y + cell_size / 2. - 6.),
+            );
+        }
+    }
+}
+
+const BAR_HEIGHT: f64 = 14.;
+const BAR_LABEL_WIDTH: f64 = 96.;
+
+/// Horizontal bars of how many cells each module filled over the remembered
+/// generations.
+pub struct UsageHistogram;
+
+impl Widget<Diagnostics> for UsageHistogram {
+    fn event(&mut self, _ctx: &mut EventCtx, _event: &Event, _data: &mut Diagnostics, _env: &Env) {}
+
+    fn lifecycle(
+        &mut self,
+        _ctx: &mut LifeCycleCtx,
+        _event: &LifeCycle,
+        _data: &Diagnostics,
+        _env: &Env,
+    ) {
+    }
+
+    fn update(
+        &mut self,
+        ctx: &mut UpdateCtx,
+        old_data: &Diagnostics,
+        data: &Diagnostics,
+        _env: &Env,
+    ) {
+        if !old_data.same(data) {
+            ctx.request_layout();
+        }
+    }
+
+    fn layout(
+        &mut self,
+        _layout_ctx: &mut LayoutCtx,
+        bc: &BoxConstraints,
+        data: &Diagnostics,
+        _env: &Env,
+    ) -> Size {
+        let rows = data.usage().len().max(1);
+        bc.constrain((bc.max().width, rows as f64 * BAR_HEIGHT))
+    }
+
+    fn paint(&mut self, ctx: &mut PaintCtx, data: &Diagnostics, _env: &Env) {
+        let size = ctx.size();
+        let usage = data.usage();
+        let total: usize = usage.values().sum();
+        let most = usage.values().copied().max().unwrap_or(0).max(1);
+        let width = (size.width - BAR_LABEL_WIDTH - 48.).max(0.);
+        for (i, (name, count)) in usage.iter().enumerate() {
+            let y = i as f64 * BAR_HEIGHT;
+            let bar = Rect::from_origin_size(
+                (BAR_LABEL_WIDTH, y + 2.),
+                (width * *count as f64 / most as f64, BAR_HEIGHT - 4.),
+            );
+            ctx.fill(bar, &Color::rgb8(10, 127, 127));
+            paint_text(ctx, name, Point::new(0., y));
+            let share = format!("{:.1}%", 100. * *count as f64 / total.max(1) as f64);
+            paint_text(ctx, &share, Point::new(bar.x1 + 4., y));
+        }
+    }
+}
+
+/// Mean cell entropy through each remembered generation, faint, with their
+/// average drawn on top.
+pub struct EntropyChart;
+
+impl Widget<Diagnostics> for EntropyChart {
+    fn event(&mut self, _ctx: &mut EventCtx, _event: &Event, _data: &mut Diagnostics, _env: &Env) {}
+
+    fn lifecycle(
+        &mut self,
+        _ctx: &mut LifeCycleCtx,
+        _event: &LifeCycle,
+        _data: &Diagnostics,
+        _env: &Env,
+    ) {
+    }
+
+    fn update(
+        &mut self,
+        ctx: &mut UpdateCtx,
+        old_data: &Diagnostics,
+        data: &Diagnostics,
+        _env: &Env,
+    ) {
+        if !old_data.same(data) {
+            ctx.request_paint();
+        }
+    }
+
+    fn layout(
+        &mut self,
+        _layout_ctx: &mut LayoutCtx,
+        bc: &BoxConstraints,
+        _data: &Diagnostics,
+        _env: &Env,
+    ) -> Size {
+        bc.constrain((bc.max().width, 100.))
+    }
+
+    fn paint(&mut self, ctx: &mut PaintCtx, data: &Diagnostics, _env: &Env) {
+        let size = ctx.size();
+        ctx.fill(size.to_rect(), &Color::grey(0.15));
+        let highest = data
+            .runs
+            .iter()
+            .flat_map(|run| run.entropy.iter().copied())
+            .fold(0., f64::max);
+        if highest == 0. {
+            return;
+        }
+        let point = |i: usize, entropy: f64| {
+            Point::new(
+                size.width * i as f64 / (ENTROPY_SAMPLES - 1) as f64,
+                size.height * (1. - entropy / highest),
+            )
+        };
+        let curve = |entropy: &[f64]| {
+            let mut path = BezPath::new();
+            for (i, &e) in entropy.iter().enumerate() {
+                if i == 0 {
+                    path.move_to(point(i, e));
+                } else {
+                    path.line_to(point(i, e));
+                }
             }
+            path
+        };
+        for run in data.runs.iter() {
+            ctx.stroke(curve(&run.entropy), &Color::rgba8(255, 255, 255, 64), 1.);
         }
+        ctx.stroke(curve(&data.mean_entropy()), &Color::rgb8(255, 128, 0), 2.);
+        paint_text(ctx, &format!("{:.2} bits", highest), Point::new(4., 2.));
     }
 }
+
+fn paint_text(ctx: &mut PaintCtx, text: &str, at: Point) {
+    let layout = ctx
+        .text()
+        .new_text_layout(text.to_string())
+        .font(FontFamily::SYSTEM_UI, 11.)
+        .text_color(Color::grey(0.9))
+        .build()
+        .unwrap();
+    ctx.draw_text(&layout, at);
+}
@@ -0,0 +1,269 @@
+use std::collections::HashMap;
+
+use druid::Color;
+use image::RgbImage;
+use rand::{rngs::StdRng, SeedableRng};
+
+use crate::{
+    data::{Canvas, CanvasPos, MAX_ATTEMPTS},
+    wave::{opposite, Contradiction, Rules, Wave},
+};
+
+pub const CHUNK_SIZE: i64 = 16;
+
+const CORNER: u64 = 1;
+const HORIZONTAL_SEAM: u64 = 2;
+const VERTICAL_SEAM: u64 = 3;
+const INTERIOR: u64 = 4;
+
+/// An endless map generated a chunk at a time.
+///
+/// Every chunk is a function of the world seed and its coordinates only, so chunks
+/// can be generated in any order and always come out the same. Neighbouring chunks
+/// agree along their seams because the seams are generated on their own first: the
+/// cells on chunk corners are picked outright, the seam cells between two corners
+/// are solved as a strip fixed at both ends, and each chunk's interior is then
+/// solved against the four seams around it. Cells that could not be solved are
+/// left empty.
+pub struct World {
+    pub rules: Rules,
+    seed: u64,
+    corners: HashMap<(i64, i64), usize>,
+    horizontal_seams: HashMap<(i64, i64), Vec<Option<usize>>>,
+    vertical_seams: HashMap<(i64, i64), Vec<Option<usize>>>,
+    chunks: HashMap<(i64, i64), Vec<Vec<Option<usize>>>>,
+}
+
+impl World {
+    pub fn new(rules: Rules, seed: u64) -> Self {
+        Self {
+            rules,
+            seed,
+            corners: HashMap::new(),
+            horizontal_seams: HashMap::new(),
+            vertical_seams: HashMap::new(),
+            chunks: HashMap::new(),
+        }
+    }
+
+    fn rng(&self, kind: u64, x: i64, y: i64, attempt: u64) -> StdRng {
+        let mut hash = splitmix(self.seed ^ splitmix(kind));
+        hash = splitmix(hash ^ x as u64);
+        hash = splitmix(hash ^ y as u64);
+        StdRng::seed_from_u64(hash.wrapping_add(attempt))
+    }
+
+    /// The tile index at world cell (`x`, `y`), or `None` if it could not be solved.
+    /// Generates the cell's chunk if needed.
+    pub fn cell(&mut self, x: i64, y: i64) -> Option<usize> {
+        self.generate(x.div_euclid(CHUNK_SIZE), y.div_euclid(CHUNK_SIZE));
+        self.generated_cell(x, y).flatten()
+    }
+
+    /// The tile index at world cell (`x`, `y`) without generating anything: `None`
+    /// while its chunk has not been generated, `Some(None)` if it could not be solved.
+    pub fn generated_cell(&self, x: i64, y: i64) -> Option<Option<usize>> {
+        let (cx, cy) = (x.div_euclid(CHUNK_SIZE), y.div_euclid(CHUNK_SIZE));
+        let (lx, ly) = (x.rem_euclid(CHUNK_SIZE), y.rem_euclid(CHUNK_SIZE));
+        let chunk = self.chunks.get(&(cx, cy))?;
+        Some(match (lx, ly) {
+            (0, 0) => Some(self.corners[&(cx, cy)]),
+            (_, 0) => self.horizontal_seams[&(cx, cy)][lx as usize - 1],
+            (0, _) => self.vertical_seams[&(cx, cy)][ly as usize - 1],
+            _ => chunk[ly as usize - 1][lx as usize - 1],
+        })
+    }
+
+    pub fn has_chunk(&self, cx: i64, cy: i64) -> bool {
+        self.chunks.contains_key(&(cx, cy))
+    }
+
+    /// Generates chunk (`cx`, `cy`), with its top and left seams, if it is not yet.
+    pub fn generate(&mut self, cx: i64, cy: i64) {
+        self.interior(cx, cy);
+    }
+
+    /// Forgets the chunks, seams and corners `keep` says no to. They come out the
+    /// same if they are generated again.
+    pub fn retain(&mut self, keep: impl Fn(i64, i64) -> bool) {
+        self.corners.retain(|&(cx, cy), _| keep(cx, cy));
+        self.horizontal_seams.retain(|&(cx, cy), _| keep(cx, cy));
+        self.vertical_seams.retain(|&(cx, cy), _| keep(cx, cy));
+        self.chunks.retain(|&(cx, cy), _| keep(cx, cy));
+    }
+
+    /// The tile on the top-left corner of chunk (`cx`, `cy`).
+    fn corner(&mut self, cx: i64, cy: i64) -> usize {
+        if let Some(&tile) = self.corners.get(&(cx, cy)) {
+            return tile;
+        }
+        let mut rng = self.rng(CORNER, cx, cy, 0);
+        let tile = rand::Rng::gen_range(&mut rng, 0, self.rules.len());
+        self.corners.insert((cx, cy), tile);
+        tile
+    }
+
+    /// The cells between the top-left and top-right corners of chunk (`cx`, `cy`).
+    fn horizontal_seam(&mut self, cx: i64, cy: i64) -> Vec<Option<usize>> {
+        if let Some(seam) = self.horizontal_seams.get(&(cx, cy)) {
+            return seam.clone();
+        }
+        let ends = (self.corner(cx, cy), self.corner(cx + 1, cy));
+        let seam = self.solve_seam(HORIZONTAL_SEAM, cx, cy, ends, false);
+        self.horizontal_seams.insert((cx, cy), seam.clone());
+        seam
+    }
+
+    /// The cells between the top-left and bottom-left corners of chunk (`cx`, `cy`).
+    fn vertical_seam(&mut self, cx: i64, cy: i64) -> Vec<Option<usize>> {
+        if let Some(seam) = self.vertical_seams.get(&(cx, cy)) {
+            return seam.clone();
+        }
+        let ends = (self.corner(cx, cy), self.corner(cx, cy + 1));
+        let seam = self.solve_seam(VERTICAL_SEAM, cx, cy, ends, true);
+        self.vertical_seams.insert((cx, cy), seam.clone());
+        seam
+    }
+
+    fn solve_seam(
+        &self,
+        kind: u64,
+        cx: i64,
+        cy: i64,
+        (start, end): (usize, usize),
+        vertical: bool,
+    ) -> Vec<Option<usize>> {
+        let len = CHUNK_SIZE as usize + 1;
+        let (rows, cols) = if vertical { (len, 1) } else { (1, len) };
+        let at = |i: usize| if vertical { (i, 0) } else { (0, i) };
+
+        for attempt in 0..MAX_ATTEMPTS {
+            let mut rng = self.rng(kind, cx, cy, attempt);
+            let mut wave = Wave::new(&self.rules, rows, cols);
+            let (r0, c0) = at(0);
+            let (r1, c1) = at(len - 1);
+            if wave.set(r0, c0, start).is_err() || wave.set(r1, c1, end).is_err() {
+                break;
+            }
+            if wave.collapse(&mut rng).is_ok() {
+                let result = wave.result();
+                return (1..len - 1)
+                    .map(|i| {
+                        let (row, col) = at(i);
+                        Some(result[row][col])
+                    })
+                    .collect();
+            }
+        }
+        vec![None; len - 2]
+    }
+
+    /// The cells of chunk (`cx`, `cy`) that are not on its top or left seam, row by
+    /// row.
+    fn interior(&mut self, cx: i64, cy: i64) -> Vec<Vec<Option<usize>>> {
+        if let Some(chunk) = self.chunks.get(&(cx, cy)) {
+            return chunk.clone();
+        }
+        let size = CHUNK_SIZE as usize - 1;
+        // The seams around the interior, in up, right, down, left order.
+        let seams = [
+            self.horizontal_seam(cx, cy),
+            self.vertical_seam(cx + 1, cy),
+            self.horizontal_seam(cx, cy + 1),
+            self.vertical_seam(cx, cy),
+        ];
+
+        let mut chunk = vec![vec![None; size]; size];
+        for attempt in 0..MAX_ATTEMPTS {
+            let mut rng = self.rng(INTERIOR, cx, cy, attempt);
+            let mut wave = Wave::new(&self.rules, size, size);
+            if self.constrain_interior(&mut wave, &seams, size).is_err() {
+                break;
+            }
+            if wave.collapse(&mut rng).is_ok() {
+                chunk = wave
+                    .result()
+                    .into_iter()
+                    .map(|row| row.into_iter().map(Some).collect())
+                    .collect();
+                break;
+            }
+        }
+        self.chunks.insert((cx, cy), chunk.clone());
+        chunk
+    }
+
+    fn constrain_interior(
+        &self,
+        wave: &mut Wave,
+        seams: &[Vec<Option<usize>>; 4],
+        size: usize,
+    ) -> Result<(), Contradiction> {
+        for i in 0..size {
+            // The interior cell touching seam cell `i` on each side.
+            let cells = [(0, i), (i, size - 1), (size - 1, i), (i, 0)];
+            for (dir, seam) in seams.iter().enumerate() {
+                if let Some(tile) = seam[i] {
+                    let (row, col) = cells[dir];
+                    let allowed = self.rules.neighbours(opposite(dir), tile);
+                    wave.restrict(row, col, allowed)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Draws the `width` by `height` cells starting at world cell (`x`, `y`).
+    pub fn render_region(&mut self, x: i64, y: i64, width: usize, height: usize) -> RgbImage {
+        let mut cells = vec![];
+        for row in 0..height {
+            for col in 0..width {
+                let tile = self.cell(x + col as i64, y + row as i64);
+                cells.push(tile.map(|tile| self.rules.tiles[tile].canvas.clone()));
+            }
+        }
+        compose(width, height, &cells)
+    }
+}
+
+/// Lays out `cells`, given row by row, as a `width` by `height` grid of tiles.
+/// Missing cells are left black.
+pub fn compose(width: usize, height: usize, cells: &[Option<Canvas>]) -> RgbImage {
+    let tile_width = cells.iter().flatten().map(|c| c.width).max().unwrap_or(1);
+    let tile_height = cells.iter().flatten().map(|c| c.height).max().unwrap_or(1);
+    let mut image = RgbImage::new((width * tile_width) as u32, (height * tile_height) as u32);
+    for (i, canvas) in cells.iter().enumerate() {
+        let canvas = match canvas {
+            Some(canvas) => canvas,
+            None => continue,
+        };
+        let (ox, oy) = ((i % width) * tile_width, (i / width) * tile_height);
+        for px in 0..canvas.width {
+            for py in 0..canvas.height {
+                let (r, g, b, _) = canvas[CanvasPos { row: px, col: py }].as_rgba8();
+                image.put_pixel((ox + px) as u32, (oy + py) as u32, image::Rgb([r, g, b]));
+            }
+        }
+    }
+    image
+}
+
+/// The average colour of a canvas, used to draw tiles smaller than their pixels.
+pub fn average_color(canvas: &Canvas) -> Color {
+    let (mut r, mut g, mut b) = (0u32, 0u32, 0u32);
+    for color in canvas.storage.iter() {
+        let (cr, cg, cb, _) = color.as_rgba8();
+        r += cr as u32;
+        g += cg as u32;
+        b += cb as u32;
+    }
+    let n = canvas.storage.len().max(1) as u32;
+    Color::rgb8((r / n) as u8, (g / n) as u8, (b / n) as u8)
+}
+
+fn splitmix(mut x: u64) -> u64 {
+    x = x.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    x = (x ^ (x >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    x = (x ^ (x >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    x ^ (x >> 31)
+}